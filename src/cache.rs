@@ -0,0 +1,409 @@
+//! Content-addressed caching for code block handlers.
+//!
+//! [`CachedHandler`] wraps any [`CodeBlockHandler`] and remembers its output,
+//! keyed by a handler identity, the language, the full info string, the
+//! block's location (source path and line) and the code itself. Entries live in an in-memory LRU and,
+//! optionally, in a directory on disk so they survive between builds.
+
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Mutex;
+
+use crate::Result;
+use crate::fence::CodeBlockInfo;
+use crate::handler::{CodeBlockContext, CodeBlockHandler, CodeBlockOutput, HeadInjection};
+
+/// Default number of entries kept in memory.
+const DEFAULT_CAPACITY: usize = 4096;
+
+/// First line of every on-disk entry, bumped when the format changes.
+const DISK_MAGIC: &str = "marq-cache 1";
+
+/// A [`CodeBlockHandler`] wrapper that caches the wrapped handler's output.
+///
+/// The `id` names the handler and its configuration: change it (e.g.
+/// `"arborium-v2"`) whenever the wrapped handler would render the same code
+/// differently, since cached entries are never invalidated otherwise. Errors
+/// are not cached.
+///
+/// Files the wrapped handler reads itself, such as the CSV file named by a
+/// table's `src=`, are not part of the key: edits to them are not seen until
+/// the cache is cleared, so don't wrap handlers whose output depends on them.
+///
+/// Disk entries are read and written with blocking file I/O. I/O failures are
+/// treated as cache misses. Outputs carrying [`StaticAsset`](crate::StaticAsset)s
+/// are only cached in memory.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// let handler = CachedHandler::new("arborium", ArboriumHandler::new())
+///     .with_disk_cache("target/marq-cache");
+/// let opts = RenderOptions::new().with_default_handler(handler);
+/// ```
+pub struct CachedHandler<H> {
+    inner: H,
+    id: String,
+    memory: Mutex<Lru>,
+    disk_dir: Option<PathBuf>,
+}
+
+impl<H: CodeBlockHandler> CachedHandler<H> {
+    /// Wrap `inner`, identified by `id` in cache keys.
+    pub fn new(id: impl Into<String>, inner: H) -> Self {
+        Self {
+            inner,
+            id: id.into(),
+            memory: Mutex::new(Lru::new(DEFAULT_CAPACITY)),
+            disk_dir: None,
+        }
+    }
+
+    /// Set how many entries the in-memory LRU keeps (default 4096).
+    pub fn with_capacity(self, capacity: usize) -> Self {
+        Self {
+            memory: Mutex::new(Lru::new(capacity)),
+            ..self
+        }
+    }
+
+    /// Also persist entries as files in `dir`, which is created on first write.
+    pub fn with_disk_cache(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.disk_dir = Some(dir.into());
+        self
+    }
+
+    /// The wrapped handler.
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+
+    /// The key for a block; `line` is 0 when rendered without a context.
+    fn key(
+        &self,
+        info: &CodeBlockInfo,
+        source_path: Option<&str>,
+        line: usize,
+        code: &str,
+    ) -> u128 {
+        let mut hasher = Fnv128::new();
+        for part in [
+            self.id.as_str(),
+            info.language.as_str(),
+            info.raw.as_str(),
+            source_path.unwrap_or_default(),
+            &line.to_string(),
+            code,
+        ] {
+            hasher.write(part.as_bytes());
+            hasher.write(&[0xff]);
+        }
+        hasher.finish()
+    }
+
+    fn lookup(&self, key: u128) -> Option<CodeBlockOutput> {
+        if let Some(output) = self.memory.lock().unwrap().get(key) {
+            return Some(output);
+        }
+        let output = decode(&std::fs::read_to_string(self.disk_path(key)?).ok()?)?;
+        self.memory.lock().unwrap().insert(key, output.clone());
+        Some(output)
+    }
+
+    fn store(&self, key: u128, output: &CodeBlockOutput) {
+        self.memory.lock().unwrap().insert(key, output.clone());
+        // The disk format is text-only, so outputs carrying (possibly binary)
+        // assets are only cached in memory.
+        if !output.assets.is_empty() {
+            return;
+        }
+        if let Some(path) = self.disk_path(key) {
+            // Write to a temporary file first so concurrent readers never see
+            // a partial entry.
+            let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+            let _ = std::fs::create_dir_all(path.parent().unwrap())
+                .and_then(|()| std::fs::write(&tmp, encode(output)))
+                .and_then(|()| std::fs::rename(&tmp, &path));
+        }
+    }
+
+    fn disk_path(&self, key: u128) -> Option<PathBuf> {
+        let dir = self.disk_dir.as_ref()?;
+        let name = format!("{key:032x}");
+        Some(dir.join(&name[..2]).join(&name[2..]))
+    }
+
+    async fn render_cached(
+        &self,
+        key: u128,
+        render: impl Future<Output = Result<CodeBlockOutput>>,
+    ) -> Result<CodeBlockOutput> {
+        if let Some(output) = self.lookup(key) {
+            return Ok(output);
+        }
+        let output = render.await?;
+        self.store(key, &output);
+        Ok(output)
+    }
+}
+
+impl<H: CodeBlockHandler> CodeBlockHandler for CachedHandler<H> {
+    fn render<'a>(
+        &'a self,
+        language: &'a str,
+        code: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
+        Box::pin(async move {
+            let key = self.key(&CodeBlockInfo::from_language(language), None, 0, code);
+            self.render_cached(key, self.inner.render(language, code))
+                .await
+        })
+    }
+
+    fn render_with_info<'a>(
+        &'a self,
+        info: &'a CodeBlockInfo,
+        code: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
+        Box::pin(async move {
+            let key = self.key(info, None, 0, code);
+            self.render_cached(key, self.inner.render_with_info(info, code))
+                .await
+        })
+    }
+
+    fn render_with_context<'a>(
+        &'a self,
+        context: &'a CodeBlockContext<'a>,
+        code: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
+        Box::pin(async move {
+            // The location is part of the key because handlers may resolve
+            // relative assets against the source path, or report the line.
+            let key = self.key(context.info, context.source_path, context.line, code);
+            self.render_cached(key, self.inner.render_with_context(context, code))
+                .await
+        })
+    }
+}
+
+/// A least-recently-used map from cache keys to outputs.
+struct Lru {
+    capacity: usize,
+    entries: HashMap<u128, (CodeBlockOutput, u64)>,
+    /// Keys by last use, oldest first
+    order: BTreeMap<u64, u128>,
+    tick: u64,
+}
+
+impl Lru {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn get(&mut self, key: u128) -> Option<CodeBlockOutput> {
+        self.tick += 1;
+        let (output, used) = self.entries.get_mut(&key)?;
+        self.order.remove(used);
+        *used = self.tick;
+        self.order.insert(self.tick, key);
+        Some(output.clone())
+    }
+
+    fn insert(&mut self, key: u128, output: CodeBlockOutput) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+        if let Some((_, used)) = self.entries.insert(key, (output, self.tick)) {
+            self.order.remove(&used);
+        }
+        self.order.insert(self.tick, key);
+
+        while self.entries.len() > self.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+/// 128-bit FNV-1a, stable across builds and platforms.
+struct Fnv128(u128);
+
+impl Fnv128 {
+    const OFFSET: u128 = 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d;
+    const PRIME: u128 = 0x0000_0000_0100_0000_0000_0000_0000_013b;
+
+    fn new() -> Self {
+        Self(Self::OFFSET)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u128::from(byte);
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    fn finish(&self) -> u128 {
+        self.0
+    }
+}
+
+/// Serialize an output as a header line, length-prefixed injections, then the HTML.
+fn encode(output: &CodeBlockOutput) -> String {
+    let mut out = format!("{DISK_MAGIC} {}\n", output.head_injections.len());
+    for injection in &output.head_injections {
+        out.push_str(&format!(
+            "{} {}\n{}{}",
+            injection.key.len(),
+            injection.html.len(),
+            injection.key,
+            injection.html
+        ));
+    }
+    out.push_str(&output.html);
+    out
+}
+
+fn decode(data: &str) -> Option<CodeBlockOutput> {
+    let (header, mut rest) = data.split_once('\n')?;
+    let count: usize = header.strip_prefix(DISK_MAGIC)?.trim().parse().ok()?;
+
+    let mut head_injections = Vec::with_capacity(count);
+    for _ in 0..count {
+        let (lengths, body) = rest.split_once('\n')?;
+        let (key_len, html_len) = lengths.split_once(' ')?;
+        let key_len: usize = key_len.parse().ok()?;
+        let html_len: usize = html_len.parse().ok()?;
+        head_injections.push(HeadInjection {
+            key: body.get(..key_len)?.to_string(),
+            html: body.get(key_len..key_len + html_len)?.to_string(),
+        });
+        rest = body.get(key_len + html_len..)?;
+    }
+
+    Some(CodeBlockOutput {
+        html: rest.to_string(),
+        head_injections,
+        assets: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct CountingHandler(AtomicUsize);
+
+    impl CodeBlockHandler for CountingHandler {
+        fn render<'a>(
+            &'a self,
+            language: &'a str,
+            code: &'a str,
+        ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
+            Box::pin(async move {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Ok(CodeBlockOutput {
+                    html: format!("<pre data-lang=\"{language}\">{code}</pre>"),
+                    head_injections: vec![HeadInjection {
+                        key: "style".to_string(),
+                        html: "<style>\npre {}\n</style>".to_string(),
+                    }],
+                    assets: Vec::new(),
+                })
+            })
+        }
+    }
+
+    fn calls(handler: &CachedHandler<CountingHandler>) -> usize {
+        handler.inner().0.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn test_memory_cache_and_eviction() {
+        let handler =
+            CachedHandler::new("test", CountingHandler(AtomicUsize::new(0))).with_capacity(1);
+
+        let first = handler.render("rust", "a").await.unwrap();
+        let again = handler.render("rust", "a").await.unwrap();
+        assert_eq!(first.html, again.html);
+        assert_eq!(again.head_injections[0].key, "style");
+        assert_eq!(calls(&handler), 1);
+
+        // A different language is a different key, and evicts "a"
+        handler.render("python", "a").await.unwrap();
+        handler.render("rust", "a").await.unwrap();
+        assert_eq!(calls(&handler), 3);
+    }
+
+    #[tokio::test]
+    async fn test_context_location_is_part_of_the_key() {
+        let handler = CachedHandler::new("test", CountingHandler(AtomicUsize::new(0)));
+        let info = CodeBlockInfo::parse("rust");
+        let mut context = CodeBlockContext::new(&info);
+        context.source_path = Some("docs/a.md");
+
+        handler.render_with_context(&context, "a").await.unwrap();
+        handler.render_with_context(&context, "a").await.unwrap();
+        assert_eq!(calls(&handler), 1);
+
+        context.line = 12;
+        handler.render_with_context(&context, "a").await.unwrap();
+        context.source_path = Some("docs/b.md");
+        handler.render_with_context(&context, "a").await.unwrap();
+        assert_eq!(calls(&handler), 3);
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache_survives_new_handler() {
+        let dir = std::env::temp_dir().join(format!("marq-cache-test-{}", std::process::id()));
+        let info = CodeBlockInfo::parse("rust title=\"a.rs\"");
+
+        let handler =
+            CachedHandler::new("test", CountingHandler(AtomicUsize::new(0))).with_disk_cache(&dir);
+        let written = handler.render_with_info(&info, "fn a() {}").await.unwrap();
+
+        let handler =
+            CachedHandler::new("test", CountingHandler(AtomicUsize::new(0))).with_disk_cache(&dir);
+        let read = handler.render_with_info(&info, "fn a() {}").await.unwrap();
+        assert_eq!(calls(&handler), 0);
+        assert_eq!(read.html, written.html);
+        assert_eq!(read.head_injections[0].html, "<style>\npre {}\n</style>");
+
+        // Another handler id never sees these entries
+        let other =
+            CachedHandler::new("other", CountingHandler(AtomicUsize::new(0))).with_disk_cache(&dir);
+        other.render_with_info(&info, "fn a() {}").await.unwrap();
+        assert_eq!(calls(&other), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_encode_decode_roundtrip() {
+        let output = CodeBlockOutput {
+            html: "<div>\n1 2\n</div>".to_string(),
+            head_injections: vec![HeadInjection {
+                key: "k 1".to_string(),
+                html: "a\nb".to_string(),
+            }],
+            assets: Vec::new(),
+        };
+        let decoded = decode(&encode(&output)).unwrap();
+        assert_eq!(decoded.html, output.html);
+        assert_eq!(decoded.head_injections[0].key, "k 1");
+        assert_eq!(decoded.head_injections[0].html, "a\nb");
+        assert!(decode("not a cache entry").is_none());
+    }
+}
@@ -0,0 +1,161 @@
+//! Code groups: consecutive fenced blocks rendered as one tabbed widget.
+//!
+//! A block joins a group with the `group` flag, or `group=name` to keep two
+//! adjacent groups apart. Each block is still an ordinary fenced code block in
+//! the source and AST, and is rendered by its own handler:
+//!
+//! ````text
+//! ```bash,group title="Bash"
+//! curl -fsSL https://example.com/install.sh | sh
+//! ```
+//! ```fish,group title="Fish"
+//! curl -fsSL https://example.com/install.sh | sh
+//! ```
+//! ````
+//!
+//! The `title=` attribute labels the tab (falling back to the language). As
+//! with tabbed compare blocks, picking a tab switches every group on the page
+//! that has a tab with the same label, and the choice is remembered.
+
+use crate::fence::CodeBlockInfo;
+use crate::handler::{HeadInjection, html_escape, tab_keys, tab_list, tabs_injection};
+
+/// The group a block belongs to: `Some("")` for the bare `group` flag,
+/// `Some(name)` for `group=name`, `None` if it isn't grouped.
+pub(crate) fn group_name(info: &CodeBlockInfo) -> Option<&str> {
+    info.attr("group")
+        .or_else(|| info.has_flag("group").then_some(""))
+}
+
+/// Info passed to a grouped block's handler: the title moves to the tab, so
+/// it is dropped here to avoid rendering it twice.
+pub(crate) fn handler_info(info: &CodeBlockInfo) -> CodeBlockInfo {
+    let mut info = info.clone();
+    info.attrs.retain(|(key, _)| key != "title");
+    info
+}
+
+/// Rendered blocks waiting to be emitted as one group.
+#[derive(Default)]
+pub(crate) struct CodeGroup {
+    name: String,
+    tabs: Vec<(String, String)>,
+}
+
+impl CodeGroup {
+    /// Add a rendered block, labelled by its title or language.
+    pub(crate) fn push(&mut self, info: &CodeBlockInfo, html: String) {
+        if self.tabs.is_empty() {
+            self.name = group_name(info).unwrap_or_default().to_string();
+        }
+        let label = info
+            .attr("title")
+            .filter(|title| !title.is_empty())
+            .unwrap_or(if info.language.is_empty() {
+                "text"
+            } else {
+                &info.language
+            });
+        self.tabs.push((label.to_string(), html));
+    }
+
+    /// Whether a block with this info continues the group.
+    pub(crate) fn continues_with(&self, info: &CodeBlockInfo) -> bool {
+        group_name(info) == Some(self.name.as_str())
+    }
+
+    /// Render the group and reset it. Returns nothing if the group is empty.
+    pub(crate) fn finish(&mut self) -> Option<(String, HeadInjection)> {
+        if self.tabs.is_empty() {
+            return None;
+        }
+        let tabs = std::mem::take(&mut self.tabs);
+        let keys = tab_keys(tabs.iter().map(|(label, _)| label.as_str()));
+
+        let mut html = String::from("<div class=\"code-group\" data-tabs=\"code-group\">");
+        html.push_str(&tab_list(
+            "code-group",
+            keys.iter()
+                .map(String::as_str)
+                .zip(tabs.iter().map(|(label, _)| label.as_str())),
+        ));
+        for (key, (_, rendered)) in keys.iter().zip(&tabs) {
+            html.push_str(&format!(
+                "<div class=\"code-group-panel\" role=\"tabpanel\" data-tab=\"{}\">",
+                html_escape(key)
+            ));
+            html.push_str(rendered);
+            html.push_str("</div>");
+        }
+        html.push_str("</div>\n");
+
+        Some((html, tabs_injection()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_membership() {
+        assert_eq!(group_name(&CodeBlockInfo::parse("bash,group")), Some(""));
+        assert_eq!(
+            group_name(&CodeBlockInfo::parse("bash group=install")),
+            Some("install")
+        );
+        assert_eq!(group_name(&CodeBlockInfo::parse("bash")), None);
+
+        let mut group = CodeGroup::default();
+        group.push(&CodeBlockInfo::parse("bash group=install"), String::new());
+        assert!(group.continues_with(&CodeBlockInfo::parse("fish group=install")));
+        assert!(!group.continues_with(&CodeBlockInfo::parse("fish,group")));
+        assert!(!group.continues_with(&CodeBlockInfo::parse("fish")));
+    }
+
+    #[test]
+    fn test_group_labels() {
+        let mut group = CodeGroup::default();
+        group.push(
+            &CodeBlockInfo::parse(r#"bash,group title="Bash & zsh""#),
+            "<pre>a</pre>".to_string(),
+        );
+        group.push(
+            &CodeBlockInfo::parse("fish,group"),
+            "<pre>b</pre>".to_string(),
+        );
+
+        let (html, injection) = group.finish().unwrap();
+        assert!(
+            html.contains(
+                r#"aria-selected="true" data-tab="Bash &amp; zsh">Bash &amp; zsh</button>"#
+            )
+        );
+        assert!(html.contains(r#"aria-selected="false" data-tab="fish">fish</button>"#));
+        assert!(html.contains(r#"data-tab="fish"><pre>b</pre></div>"#));
+        assert_eq!(injection.key, "tabs");
+        assert!(group.finish().is_none());
+    }
+
+    #[test]
+    fn test_repeated_labels_get_distinct_tabs() {
+        let mut group = CodeGroup::default();
+        for code in ["a", "b", "c"] {
+            group.push(
+                &CodeBlockInfo::parse("bash,group"),
+                format!("<pre>{code}</pre>"),
+            );
+        }
+
+        let (html, _) = group.finish().unwrap();
+        assert!(html.contains(r#"data-tab="bash">bash</button>"#), "{html}");
+        assert!(
+            html.contains(r#"data-tab="bash-2">bash</button>"#),
+            "{html}"
+        );
+        assert!(
+            html.contains(r#"data-tab="bash-3"><pre>c</pre></div>"#),
+            "{html}"
+        );
+    }
+}
@@ -0,0 +1,266 @@
+//! Rust doc-test extraction from code samples.
+//!
+//! Rust code blocks follow rustdoc's conventions: fence flags such as
+//! `rust,no_run` or `rust,should_panic` say how the example is tested, and
+//! lines starting with `# ` are compiled but hidden from readers (`##` escapes
+//! a literal `#`).
+//!
+//! [`Document::doc_tests`] collects a document's Rust samples as [`DocTest`]s
+//! for a custom harness, and [`doc_test_file`] writes them out as a Rust source
+//! file of doc comments, so including that file in a library crate makes
+//! `cargo test --doc` compile and run every example with rustdoc's own
+//! handling of each flag.
+
+use crate::fence::CodeBlockInfo;
+use crate::render::{CodeSample, Document};
+
+/// A Rust example extracted from a markdown code block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocTest {
+    /// Identifier unique within the document (e.g., "line_12", or
+    /// "line_3_setup_md" for a block from an included `setup.md`)
+    pub name: String,
+    /// Included file the code block came from, or `None` for the document
+    /// itself
+    pub file: Option<String>,
+    /// Line number where the code block starts (1-indexed, within `file`)
+    pub line: usize,
+    /// The full source to compile, with hidden lines included and their `# ` removed
+    pub code: String,
+    /// Not compiled or run (`ignore`)
+    pub ignore: bool,
+    /// Compiled but not run (`no_run`)
+    pub no_run: bool,
+    /// Expected to panic when run (`should_panic`)
+    pub should_panic: bool,
+    /// Expected to fail to compile (`compile_fail`)
+    pub compile_fail: bool,
+    /// Edition from an `edition2021`-style flag
+    pub edition: Option<String>,
+}
+
+impl DocTest {
+    /// Build a doc-test from a Rust code sample; `None` for other languages.
+    pub fn from_sample(sample: &CodeSample) -> Option<Self> {
+        if !is_rust(&sample.info) {
+            return None;
+        }
+        let info = &sample.info;
+        let code = sample
+            .code
+            .trim_end_matches('\n')
+            .lines()
+            .map(|line| match classify(line) {
+                Line::Shown(line) | Line::Hidden(line) => line,
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        Some(Self {
+            name: test_name(sample.file.as_deref(), sample.line),
+            file: sample.file.clone(),
+            line: sample.line,
+            code,
+            ignore: info.has_flag("ignore"),
+            no_run: info.has_flag("no_run"),
+            should_panic: info.has_flag("should_panic"),
+            compile_fail: info.has_flag("compile_fail"),
+            edition: info
+                .flags
+                .iter()
+                .find_map(|flag| flag.strip_prefix("edition"))
+                .map(str::to_owned),
+        })
+    }
+
+    /// The fence info string rustdoc needs to test this example the same way.
+    fn fence(&self) -> String {
+        let mut fence = String::from("rust");
+        for (on, flag) in [
+            (self.ignore, "ignore"),
+            (self.no_run, "no_run"),
+            (self.should_panic, "should_panic"),
+            (self.compile_fail, "compile_fail"),
+        ] {
+            if on {
+                fence.push(',');
+                fence.push_str(flag);
+            }
+        }
+        if let Some(edition) = &self.edition {
+            fence.push_str(",edition");
+            fence.push_str(edition);
+        }
+        fence
+    }
+}
+
+impl Document {
+    /// The document's Rust code samples as doc-tests, in document order.
+    pub fn doc_tests(&self) -> Vec<DocTest> {
+        let mut seen = std::collections::HashSet::new();
+        self.code_samples
+            .iter()
+            .filter_map(DocTest::from_sample)
+            .map(|mut test| {
+                // Distinct paths can still sanitize to the same name
+                let base = test.name.clone();
+                let mut n = 1;
+                while !seen.insert(test.name.clone()) {
+                    n += 1;
+                    test.name = format!("{base}_{n}");
+                }
+                test
+            })
+            .collect()
+    }
+}
+
+/// Write doc-tests as a Rust source file of doc comments, one empty module
+/// per example. `source` names the markdown file in the generated comments.
+///
+/// Include the file as a module of a library crate and run
+/// `cargo test --doc` to compile and run every example.
+pub fn doc_test_file(source: &str, tests: &[DocTest]) -> String {
+    let mut out = format!(
+        "//! Doc-tests extracted from `{source}`.\n//!\n//! Generated by marq; do not edit.\n"
+    );
+    for test in tests {
+        out.push_str(&format!(
+            "\n/// `{}` line {}\n///\n/// ```{}\n",
+            test.file.as_deref().unwrap_or(source),
+            test.line,
+            test.fence()
+        ));
+        for line in test.code.lines() {
+            // Escape lines rustdoc would otherwise hide
+            let trimmed = line.trim_start();
+            let escape = trimmed == "#" || trimmed.starts_with("# ") || trimmed.starts_with("##");
+            let line = if escape {
+                line.replacen('#', "##", 1)
+            } else {
+                line.to_string()
+            };
+            if line.is_empty() {
+                out.push_str("///\n");
+            } else {
+                out.push_str(&format!("/// {line}\n"));
+            }
+        }
+        out.push_str(&format!("/// ```\npub mod {} {{}}\n", test.name));
+    }
+    out
+}
+
+/// A module name for a block at `line` of `file`: `line_N` for the document
+/// itself, suffixed with the file's path as an identifier for included files.
+fn test_name(file: Option<&str>, line: usize) -> String {
+    match file {
+        Some(file) => {
+            let suffix: String = file
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() {
+                        c.to_ascii_lowercase()
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            format!("line_{line}_{}", suffix.trim_matches('_'))
+        }
+        None => format!("line_{line}"),
+    }
+}
+
+/// Whether a code block is tested as Rust.
+pub(crate) fn is_rust(info: &CodeBlockInfo) -> bool {
+    matches!(info.language.as_str(), "rust" | "rs")
+}
+
+/// Remove hidden lines from Rust code for display, unescaping `##`.
+pub(crate) fn strip_hidden_lines(code: &str) -> String {
+    code.lines()
+        .filter_map(|line| match classify(line) {
+            Line::Shown(line) => Some(line),
+            Line::Hidden(_) => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+enum Line<'a> {
+    Shown(std::borrow::Cow<'a, str>),
+    Hidden(std::borrow::Cow<'a, str>),
+}
+
+/// Classify a line the way rustdoc does.
+fn classify(line: &str) -> Line<'_> {
+    let trimmed = line.trim();
+    if trimmed.starts_with("##") {
+        Line::Shown(line.replacen("##", "#", 1).into())
+    } else if let Some(hidden) = trimmed.strip_prefix("# ") {
+        Line::Hidden(hidden.into())
+    } else if trimmed == "#" {
+        Line::Hidden("".into())
+    } else {
+        Line::Shown(line.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(line: usize, info: &str, code: &str) -> CodeSample {
+        let info = CodeBlockInfo::parse(info);
+        CodeSample {
+            line,
+            language: info.raw.clone(),
+            info,
+            code: code.to_string(),
+            file: None,
+        }
+    }
+
+    #[test]
+    fn test_hidden_lines() {
+        let code = "# fn main() {\nlet x = 1;\n    ## not hidden\n#\n# }";
+        assert_eq!(strip_hidden_lines(code), "let x = 1;\n    # not hidden");
+
+        let test = DocTest::from_sample(&sample(3, "rust", code)).unwrap();
+        assert_eq!(test.code, "fn main() {\nlet x = 1;\n    # not hidden\n\n}");
+    }
+
+    #[test]
+    fn test_flags_and_languages() {
+        let test =
+            DocTest::from_sample(&sample(7, "rust,no_run,should_panic,edition2021", "x")).unwrap();
+        assert_eq!(test.name, "line_7");
+        assert!(test.no_run && test.should_panic && !test.ignore && !test.compile_fail);
+        assert_eq!(test.edition.as_deref(), Some("2021"));
+        assert_eq!(test.fence(), "rust,no_run,should_panic,edition2021");
+
+        assert!(DocTest::from_sample(&sample(1, "python", "x")).is_none());
+        assert!(
+            DocTest::from_sample(&sample(1, "rs,compile_fail", "x"))
+                .unwrap()
+                .compile_fail
+        );
+    }
+
+    #[test]
+    fn test_doc_test_file() {
+        let tests = vec![
+            DocTest::from_sample(&sample(2, "rust,ignore", "# use std::fmt;\n\nlet x = 1;\n"))
+                .unwrap(),
+            DocTest::from_sample(&sample(9, "rust", "## literal")).unwrap(),
+        ];
+        assert_eq!(
+            doc_test_file("guide.md", &tests),
+            "//! Doc-tests extracted from `guide.md`.\n//!\n//! Generated by marq; do not edit.\n\
+             \n/// `guide.md` line 2\n///\n/// ```rust,ignore\n/// use std::fmt;\n///\n/// let x = 1;\n/// ```\npub mod line_2 {}\n\
+             \n/// `guide.md` line 9\n///\n/// ```rust\n/// ## literal\n/// ```\npub mod line_9 {}\n"
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! GitHub emoji shortcode expansion.
+//!
+//! Replaces `:name:` shortcodes with the corresponding emoji, using the
+//! gemoji table compiled into the `emojis` crate. URLs and requirement
+//! markers that happen to appear in prose are left untouched.
+
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+use regex::{Captures, Regex};
+
+use crate::typography::VERBATIM_PATTERN;
+
+fn shortcode_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    // Verbatim text is matched first so that any `:name:` inside it is skipped
+    RE.get_or_init(|| {
+        Regex::new(&format!(
+            r"(?P<skip>{VERBATIM_PATTERN})|:(?P<name>[a-z0-9_+-]+):"
+        ))
+        .unwrap()
+    })
+}
+
+/// Expand emoji shortcodes in plain (unescaped) text.
+///
+/// Unknown shortcodes, and shortcodes glued to surrounding letters or digits
+/// (as in `12:30:45`), are kept verbatim.
+pub(crate) fn expand_shortcodes(text: &str) -> Cow<'_, str> {
+    if !text.contains(':') {
+        return Cow::Borrowed(text);
+    }
+
+    shortcode_regex().replace_all(text, |caps: &Captures<'_>| {
+        let whole = caps.get(0).unwrap();
+        let Some(name) = caps.name("name") else {
+            return whole.as_str().to_string();
+        };
+
+        let glued = text[..whole.start()]
+            .chars()
+            .next_back()
+            .is_some_and(char::is_alphanumeric)
+            || text[whole.end()..]
+                .chars()
+                .next()
+                .is_some_and(char::is_alphanumeric);
+        if glued {
+            return whole.as_str().to_string();
+        }
+
+        match emojis::get_by_shortcode(name.as_str()) {
+            Some(emoji) => emoji.as_str().to_string(),
+            None => whole.as_str().to_string(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expands_known_shortcodes() {
+        assert_eq!(expand_shortcodes("Ship it :rocket:!"), "Ship it 🚀!");
+        assert_eq!(expand_shortcodes(":+1: and :-1:"), "👍 and 👎");
+        assert_eq!(expand_shortcodes(":tada::sparkles:"), "🎉✨");
+    }
+
+    #[test]
+    fn test_leaves_unknown_and_glued_shortcodes() {
+        assert_eq!(expand_shortcodes(":not_an_emoji:"), ":not_an_emoji:");
+        assert_eq!(expand_shortcodes("at 10:100:30"), "at 10:100:30");
+    }
+
+    #[test]
+    fn test_skips_urls_and_req_markers() {
+        assert_eq!(
+            expand_shortcodes("see https://example.com/:rocket:/x :rocket:"),
+            "see https://example.com/:rocket:/x 🚀"
+        );
+        assert_eq!(
+            expand_shortcodes("per r[emoji:fire:] here"),
+            "per r[emoji:fire:] here"
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! Code fence info string parsing.
+//!
+//! An info string such as `rust,ignore title="main.rs" {3-5,8}` is split into
+//! a language, comma flags, `key=value` attributes and highlighted line ranges.
+
+use std::ops::RangeInclusive;
+
+/// The parsed info string of a fenced code block.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CodeBlockInfo {
+    /// The info string as written after the opening fence
+    pub raw: String,
+    /// Language identifier used to pick a handler (e.g., "rust"); empty if none
+    pub language: String,
+    /// Bare flags, from the comma list (`rust,ignore,no_run`) or later words
+    pub flags: Vec<String>,
+    /// `key=value` attributes in order of appearance, with quotes removed
+    pub attrs: Vec<(String, String)>,
+    /// Highlighted line ranges from `{3-5,8}` (1-indexed, inclusive)
+    pub highlight_lines: Vec<RangeInclusive<usize>>,
+}
+
+impl CodeBlockInfo {
+    /// Parse a code fence info string.
+    pub fn parse(info: &str) -> Self {
+        let mut parsed = CodeBlockInfo {
+            raw: info.trim().to_string(),
+            ..Default::default()
+        };
+
+        for (index, token) in tokenize(info).into_iter().enumerate() {
+            if let Some(ranges) = token.strip_prefix('{') {
+                parsed.parse_line_ranges(ranges.trim_end_matches('}'));
+            } else if index == 0 {
+                let mut items = token.split(',');
+                let language = items.next().unwrap_or_default();
+                if language.contains('=') {
+                    parsed.push_item(language);
+                } else {
+                    parsed.language = language.to_string();
+                }
+                for item in items {
+                    parsed.push_item(item);
+                }
+            } else {
+                parsed.push_item(&token);
+            }
+        }
+
+        parsed
+    }
+
+    /// Create info for a code block with only a language.
+    pub fn from_language(language: impl Into<String>) -> Self {
+        let language = language.into();
+        Self {
+            raw: language.clone(),
+            language,
+            ..Default::default()
+        }
+    }
+
+    /// Look up an attribute value (e.g., `title` for `title="main.rs"`).
+    pub fn attr(&self, key: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The block's title, from a `title=` or `file=` attribute.
+    pub fn title(&self) -> Option<&str> {
+        self.attr("title").or_else(|| self.attr("file"))
+    }
+
+    /// Whether a flag such as `ignore` is present.
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|f| f == flag)
+    }
+
+    /// Whether a (1-indexed) line is in one of the highlighted ranges.
+    pub fn is_line_highlighted(&self, line: usize) -> bool {
+        self.highlight_lines
+            .iter()
+            .any(|range| range.contains(&line))
+    }
+
+    fn push_item(&mut self, item: &str) {
+        let item = item.trim();
+        if item.is_empty() {
+            return;
+        }
+        match item.split_once('=') {
+            Some((key, value)) => self
+                .attrs
+                .push((key.to_string(), unquote(value).to_string())),
+            None => self.flags.push(item.to_string()),
+        }
+    }
+
+    fn parse_line_ranges(&mut self, ranges: &str) {
+        for part in ranges.split(',') {
+            let part = part.trim();
+            let range = match part.split_once('-') {
+                Some((start, end)) => start.trim().parse().ok().zip(end.trim().parse().ok()),
+                None => part.parse().ok().map(|line| (line, line)),
+            };
+            if let Some((start, end)) = range
+                && start <= end
+            {
+                self.highlight_lines.push(start..=end);
+            }
+        }
+    }
+}
+
+/// Split on whitespace, keeping quoted values and `{...}` groups together.
+fn tokenize(info: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    let mut in_braces = false;
+
+    for c in info.trim().chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => {
+                quote = None;
+                current.push(c);
+            }
+            (Some(_), c) => current.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                current.push(c);
+            }
+            (None, '{') if current.is_empty() => {
+                in_braces = true;
+                current.push(c);
+            }
+            (None, '}') if in_braces => {
+                in_braces = false;
+                current.push(c);
+            }
+            (None, c) if c.is_whitespace() && !in_braces => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            (None, c) => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+fn unquote(value: &str) -> &str {
+    for q in ['"', '\''] {
+        if let Some(inner) = value.strip_prefix(q).and_then(|v| v.strip_suffix(q)) {
+            return inner;
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_full_info_string() {
+        let info = CodeBlockInfo::parse(r#"rust,ignore,no_run title="src/main.rs" {3-5, 8}"#);
+        assert_eq!(info.language, "rust");
+        assert_eq!(info.flags, ["ignore", "no_run"]);
+        assert_eq!(info.attr("title"), Some("src/main.rs"));
+        assert_eq!(info.highlight_lines, [3..=5, 8..=8]);
+        assert!(info.is_line_highlighted(4));
+        assert!(!info.is_line_highlighted(6));
+    }
+
+    #[test]
+    fn test_parse_comma_attrs_and_bare_words() {
+        let info = CodeBlockInfo::parse("diff,lang=rust linenos");
+        assert_eq!(info.language, "diff");
+        assert_eq!(info.attr("lang"), Some("rust"));
+        assert!(info.has_flag("linenos"));
+    }
+
+    #[test]
+    fn test_parse_empty_and_quoted_spaces() {
+        assert_eq!(CodeBlockInfo::parse(""), CodeBlockInfo::default());
+
+        let info = CodeBlockInfo::parse("{1} caption='A, b = c'");
+        assert_eq!(info.language, "");
+        assert_eq!(info.highlight_lines, [1..=1]);
+        assert_eq!(info.attr("caption"), Some("A, b = c"));
+    }
+}
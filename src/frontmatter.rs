@@ -3,7 +3,7 @@
 //! Supports both TOML (`+++`) and YAML (`---`) frontmatter formats.
 
 use facet::Facet;
-use facet_value::Value;
+use facet_value::{DestructuredRef, Value};
 
 use crate::{Error, Result};
 
@@ -31,6 +31,16 @@ pub struct Frontmatter {
     pub extra: Value,
 }
 
+impl Frontmatter {
+    /// Look up a top-level string value in [`extra`](Self::extra).
+    pub(crate) fn extra_str(&self, key: &str) -> Option<&str> {
+        match self.extra.destructure_ref() {
+            DestructuredRef::Object(obj) => obj.get(key)?.as_string().map(|s| s.as_str()),
+            _ => None,
+        }
+    }
+}
+
 /// Type of frontmatter delimiter.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum FrontmatterFormat {
@@ -0,0 +1,356 @@
+//! Glossary extraction from definition lists and automatic term linking.
+//!
+//! Every definition-list term becomes a [`GlossaryEntry`] with an anchor on
+//! its `<dt>`. When [`RenderOptions::link_glossary_terms`](crate::RenderOptions::link_glossary_terms)
+//! is enabled, the first occurrence of each term in the rendered prose is
+//! wrapped in a link to its definition, with the definition as a tooltip.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use pulldown_cmark::{Event, Parser, Tag, TagEnd};
+
+use crate::handler::html_escape;
+use crate::headings::slugify;
+
+/// A glossary term, collected from a definition list or supplied by the caller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlossaryEntry {
+    /// The term as written in the definition list title
+    pub term: String,
+    /// The anchor ID emitted on the term's `<dt>` (e.g., "glossary-source-map")
+    pub anchor_id: String,
+    /// Link target for occurrences of the term (e.g., "#glossary-source-map")
+    pub href: String,
+    /// Plain-text content of the term's first definition, used as the tooltip
+    pub definition: String,
+    /// Line number where the term is defined (1-indexed, 0 for external entries)
+    pub line: usize,
+}
+
+impl GlossaryEntry {
+    /// Create an entry for a term defined outside the document being rendered,
+    /// such as a site-wide glossary page.
+    pub fn external(
+        term: impl Into<String>,
+        href: impl Into<String>,
+        definition: impl Into<String>,
+    ) -> Self {
+        Self {
+            term: term.into(),
+            anchor_id: String::new(),
+            href: href.into(),
+            definition: definition.into(),
+            line: 0,
+        }
+    }
+}
+
+/// The anchor ID for a glossary term.
+pub(crate) fn term_anchor(term: &str) -> String {
+    format!("glossary-{}", slugify(term))
+}
+
+/// Plain text of a definition list title, given its markdown source.
+pub(crate) fn term_text(source: &str) -> String {
+    let mut text = String::new();
+    for event in Parser::new(source.trim()) {
+        match event {
+            Event::Text(t) | Event::Code(t) => text.push_str(&t),
+            Event::SoftBreak | Event::HardBreak => text.push(' '),
+            _ => {}
+        }
+    }
+    text.trim().to_string()
+}
+
+/// Collects glossary entries from the raw event stream of a document.
+#[derive(Default)]
+pub(crate) struct GlossaryCollector {
+    entries: Vec<GlossaryEntry>,
+    /// Entry index by the source offset of the title that defines it
+    titles: HashMap<usize, usize>,
+    /// Entry waiting for its first definition
+    pending: Option<usize>,
+    /// Text of the definition currently being collected
+    definition: Option<String>,
+}
+
+impl GlossaryCollector {
+    pub(crate) fn observe(&mut self, event: &Event<'_>, range: &Range<usize>, markdown: &str) {
+        match event {
+            Event::Start(Tag::DefinitionListTitle) => {
+                self.pending = None;
+                let term = term_text(&markdown[range.clone()]);
+                if term.is_empty() {
+                    return;
+                }
+                // The first definition of a term (or of a term with the same
+                // anchor) wins
+                let anchor_id = term_anchor(&term);
+                if self
+                    .entries
+                    .iter()
+                    .any(|entry| entry.anchor_id == anchor_id)
+                {
+                    return;
+                }
+                self.entries.push(GlossaryEntry {
+                    href: format!("#{}", anchor_id),
+                    anchor_id,
+                    term,
+                    definition: String::new(),
+                    line: crate::render::offset_to_line(markdown, range.start),
+                });
+                self.pending = Some(self.entries.len() - 1);
+                self.titles.insert(range.start, self.entries.len() - 1);
+            }
+            Event::Start(Tag::DefinitionListDefinition) if self.pending.is_some() => {
+                self.definition = Some(String::new());
+            }
+            Event::End(TagEnd::DefinitionListDefinition) => {
+                if let (Some(index), Some(definition)) =
+                    (self.pending.take(), self.definition.take())
+                {
+                    self.entries[index].definition = definition.trim().to_string();
+                }
+            }
+            Event::Text(t) | Event::Code(t) => {
+                if let Some(definition) = &mut self.definition {
+                    definition.push_str(t);
+                }
+            }
+            Event::SoftBreak | Event::HardBreak => {
+                if let Some(definition) = &mut self.definition {
+                    definition.push(' ');
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The anchor ID for the definition list title starting at `offset`, if it
+    /// defines a term. Later definitions of a term get no anchor.
+    pub(crate) fn anchor_at(&self, offset: usize) -> Option<&str> {
+        let index = *self.titles.get(&offset)?;
+        Some(&self.entries[index].anchor_id)
+    }
+
+    pub(crate) fn finish(self) -> Vec<GlossaryEntry> {
+        self.entries
+    }
+}
+
+/// Elements whose text content is never linked to a glossary term.
+const SKIP_ELEMENTS: &[&str] = &[
+    "a", "abbr", "code", "pre", "kbd", "samp", "script", "style", "svg", "math", "textarea", "h1",
+    "h2", "h3", "h4", "h5", "h6", "dt",
+];
+
+/// Link the first occurrence of each glossary term in rendered HTML.
+///
+/// Terms are matched case-insensitively on word boundaries, longest first, and
+/// only in text outside links, code, headings and definition titles.
+pub(crate) fn link_terms(html: &str, entries: &[&GlossaryEntry]) -> String {
+    let mut pending: Vec<(String, &GlossaryEntry)> = entries
+        .iter()
+        .map(|entry| (html_escape(&entry.term), *entry))
+        .filter(|(term, _)| !term.is_empty())
+        .collect();
+    pending.sort_by_key(|(term, _)| std::cmp::Reverse(term.len()));
+
+    let mut out = String::with_capacity(html.len());
+    let mut skip_depth = 0usize;
+    let mut rest = html;
+
+    while !rest.is_empty() && !pending.is_empty() {
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->").map(|i| i + 3).unwrap_or(rest.len());
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if rest.starts_with('<') {
+            let end = tag_end(rest);
+            let tag = &rest[..end];
+            if let Some((name, closing)) = tag_name(tag)
+                && SKIP_ELEMENTS
+                    .iter()
+                    .any(|skip| name.eq_ignore_ascii_case(skip))
+            {
+                if closing {
+                    skip_depth = skip_depth.saturating_sub(1);
+                } else if !tag.ends_with("/>") {
+                    skip_depth += 1;
+                }
+            }
+            out.push_str(tag);
+            rest = &rest[end..];
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            if skip_depth == 0 {
+                link_text(&mut out, &rest[..end], &mut pending);
+            } else {
+                out.push_str(&rest[..end]);
+            }
+            rest = &rest[end..];
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Byte length of the tag at the start of `html`, honouring quoted attributes.
+fn tag_end(html: &str) -> usize {
+    let mut quote: Option<char> = None;
+    for (i, c) in html.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return i + 1,
+            _ => {}
+        }
+    }
+    html.len()
+}
+
+/// Name of a tag and whether it is a closing tag.
+fn tag_name(tag: &str) -> Option<(&str, bool)> {
+    let inner = tag.strip_prefix('<')?;
+    let (inner, closing) = match inner.strip_prefix('/') {
+        Some(inner) => (inner, true),
+        None => (inner, false),
+    };
+    let len = inner
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+        .unwrap_or(inner.len());
+    (len > 0).then(|| (&inner[..len], closing))
+}
+
+/// Link terms in one escaped text segment, removing each linked term from `pending`.
+fn link_text(out: &mut String, text: &str, pending: &mut Vec<(String, &GlossaryEntry)>) {
+    let bytes = text.as_bytes();
+    let mut copied = 0usize;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if pending.is_empty() {
+            break;
+        }
+
+        // Never match inside an entity such as `&amp;`
+        if c == '&' {
+            while chars.next_if(|&(_, c)| c != ';').is_some() {}
+            continue;
+        }
+
+        let at_boundary = text[..i]
+            .chars()
+            .next_back()
+            .is_none_or(|prev| !prev.is_alphanumeric());
+        if !at_boundary {
+            continue;
+        }
+
+        let found = pending.iter().position(|(term, _)| {
+            let end = i + term.len();
+            end <= bytes.len()
+                && bytes[i..end].eq_ignore_ascii_case(term.as_bytes())
+                && text[end..]
+                    .chars()
+                    .next()
+                    .is_none_or(|next| !next.is_alphanumeric())
+        });
+        let Some(index) = found else {
+            continue;
+        };
+
+        let (term, entry) = pending.remove(index);
+        let end = i + term.len();
+        out.push_str(&text[copied..i]);
+        push_term_link(out, &text[i..end], entry);
+        copied = end;
+        while chars.next_if(|&(j, _)| j < end).is_some() {}
+    }
+
+    out.push_str(&text[copied..]);
+}
+
+fn push_term_link(out: &mut String, text: &str, entry: &GlossaryEntry) {
+    let abbr = if entry.definition.is_empty() {
+        format!("<abbr>{}</abbr>", text)
+    } else {
+        format!(
+            "<abbr title=\"{}\">{}</abbr>",
+            html_escape(&entry.definition),
+            text
+        )
+    };
+    if entry.href.is_empty() {
+        out.push_str(&abbr);
+    } else {
+        out.push_str(&format!(
+            "<a class=\"glossary-link\" href=\"{}\">{}</a>",
+            html_escape(&entry.href),
+            abbr
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(term: &str, definition: &str) -> GlossaryEntry {
+        GlossaryEntry::external(term, format!("#{}", term_anchor(term)), definition)
+    }
+
+    #[test]
+    fn test_term_text_strips_inline_markup() {
+        assert_eq!(term_text("**Source map**\n"), "Source map");
+        assert_eq!(term_text("`render` call\n"), "render call");
+    }
+
+    #[test]
+    fn test_link_terms_first_occurrence_only() {
+        let source_map = entry("Source map", "Maps HTML to markdown.");
+        let html = link_terms(
+            "<p>A source map here and a source map there.</p>",
+            &[&source_map],
+        );
+        assert_eq!(
+            html,
+            "<p>A <a class=\"glossary-link\" href=\"#glossary-source-map\"><abbr title=\"Maps HTML to markdown.\">source map</abbr></a> here and a source map there.</p>"
+        );
+    }
+
+    #[test]
+    fn test_link_terms_skips_code_links_and_headings() {
+        let rule = entry("rule", "");
+        let html = link_terms(
+            "<h2 id=\"rule\">rule</h2><p><code>rule</code> <a href=\"x\">rule</a> rules rule</p>",
+            &[&rule],
+        );
+        assert!(
+            html.ends_with(
+                "rules <a class=\"glossary-link\" href=\"#glossary-rule\"><abbr>rule</abbr></a></p>"
+            ),
+            "{html}"
+        );
+    }
+
+    #[test]
+    fn test_link_terms_prefers_longest_term() {
+        let map = entry("map", "");
+        let source_map = entry("source map", "");
+        let html = link_terms("<p>The source map.</p>", &[&map, &source_map]);
+        assert!(html.contains("href=\"#glossary-source-map\""), "{html}");
+        assert!(!html.contains("href=\"#glossary-map\""), "{html}");
+    }
+
+    #[test]
+    fn test_link_terms_ignores_entities() {
+        let amp = entry("amp", "");
+        assert_eq!(link_terms("<p>a &amp; b</p>", &[&amp]), "<p>a &amp; b</p>");
+    }
+}
@@ -4,10 +4,13 @@
 //! custom code block rendering (syntax highlighting, diagram rendering, etc.)
 
 use std::future::Future;
+use std::ops::Range;
 use std::pin::Pin;
 use std::sync::Arc;
 
 use crate::Result;
+use crate::fence::CodeBlockInfo;
+use crate::frontmatter::Frontmatter;
 use crate::reqs::ReqDefinition;
 
 /// An HTML snippet to inject into the page's `<head>` (or body end).
@@ -15,6 +18,7 @@ use crate::reqs::ReqDefinition;
 /// Multiple handlers can request injections; they are deduplicated by `key`
 /// so that e.g. the Mermaid.js loader script is only included once even if
 /// multiple mermaid code blocks appear in a document.
+#[derive(Debug, Clone)]
 pub struct HeadInjection {
     /// Unique key for deduplication (e.g., "mermaid").
     pub key: String,
@@ -22,15 +26,50 @@ pub struct HeadInjection {
     pub html: String,
 }
 
+/// A file a handler needs served alongside the page (a script, a stylesheet, a font).
+///
+/// Assets are collected on [`Document::assets`](crate::Document::assets),
+/// deduplicated by `name`, so a site generator can write them to disk instead
+/// of pages loading them from a CDN. The handler is told where the files will
+/// be served from (e.g. [`MermaidHandler::with_module_url`](crate::MermaidHandler::with_module_url)).
+#[derive(Debug, Clone)]
+pub struct StaticAsset {
+    /// Logical file name, unique per asset (e.g., "mermaid/mermaid.esm.min.mjs").
+    pub name: String,
+    /// MIME type (e.g., "text/javascript").
+    pub content_type: String,
+    /// File contents, shared between the blocks that use the asset.
+    pub bytes: Arc<[u8]>,
+}
+
+impl StaticAsset {
+    /// Create an asset from its name, MIME type and contents.
+    pub fn new(
+        name: impl Into<String>,
+        content_type: impl Into<String>,
+        bytes: impl Into<Arc<[u8]>>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            content_type: content_type.into(),
+            bytes: bytes.into(),
+        }
+    }
+}
+
 /// The output of a code block handler.
 ///
 /// Contains the rendered HTML that replaces the code block, plus optional
-/// [`HeadInjection`]s that the caller should include in the page.
+/// [`HeadInjection`]s that the caller should include in the page and
+/// [`StaticAsset`]s it should serve.
+#[derive(Debug, Clone)]
 pub struct CodeBlockOutput {
     /// HTML where the code block appeared.
     pub html: String,
     /// Additional page resources (scripts, stylesheets, etc.).
     pub head_injections: Vec<HeadInjection>,
+    /// Files referenced by the HTML or head injections.
+    pub assets: Vec<StaticAsset>,
 }
 
 impl From<String> for CodeBlockOutput {
@@ -38,6 +77,53 @@ impl From<String> for CodeBlockOutput {
         Self {
             html,
             head_injections: vec![],
+            assets: vec![],
+        }
+    }
+}
+
+/// Where a code block appears and what surrounds it.
+///
+/// Passed to [`CodeBlockHandler::render_with_context`]. With includes, the
+/// source path and span point into the included file the block came from.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct CodeBlockContext<'a> {
+    /// The parsed info string
+    pub info: &'a CodeBlockInfo,
+    /// Path of the file containing the block, if known
+    pub source_path: Option<&'a str>,
+    /// 1-indexed line of the opening fence
+    pub line: usize,
+    /// Byte span of the whole block, fences included
+    pub span: Range<usize>,
+    /// The document's frontmatter, if it has one
+    pub frontmatter: Option<&'a Frontmatter>,
+    /// Whether the block is part of a requirement's content
+    pub in_req: bool,
+    /// Whether the block is inside a blockquote
+    pub in_blockquote: bool,
+}
+
+impl<'a> CodeBlockContext<'a> {
+    /// Create a context with only an info string, e.g. for calling a handler directly.
+    pub fn new(info: &'a CodeBlockInfo) -> Self {
+        Self {
+            info,
+            source_path: None,
+            line: 1,
+            span: 0..0,
+            frontmatter: None,
+            in_req: false,
+            in_blockquote: false,
+        }
+    }
+
+    /// The block's location as `path:line`, or `line N` without a source path.
+    pub fn location(&self) -> String {
+        match self.source_path {
+            Some(path) => format!("{}:{}", path, self.line),
+            None => format!("line {}", self.line),
         }
     }
 }
@@ -61,6 +147,32 @@ pub trait CodeBlockHandler: Send + Sync {
         language: &'a str,
         code: &'a str,
     ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>>;
+
+    /// Render a code block with its full, parsed info string.
+    ///
+    /// The default forwards to [`render`](Self::render) with just the language;
+    /// override it to support flags, attributes such as `title="main.rs"`, or
+    /// highlighted lines.
+    fn render_with_info<'a>(
+        &'a self,
+        info: &'a CodeBlockInfo,
+        code: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
+        self.render(&info.language, code)
+    }
+
+    /// Render a code block knowing where it appears in the document.
+    ///
+    /// This is what [`render`](crate::render()) calls. The default forwards to
+    /// [`render_with_info`](Self::render_with_info); override it to resolve
+    /// assets relative to the source file or to report errors at `file:line`.
+    fn render_with_context<'a>(
+        &'a self,
+        context: &'a CodeBlockContext<'a>,
+        code: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
+        self.render_with_info(context.info, code)
+    }
 }
 
 /// Type alias for a boxed code block handler.
@@ -114,6 +226,17 @@ pub trait InlineCodeHandler: Send + Sync {
     /// # Returns
     /// The rendered HTML string. Return `None` to use the default rendering.
     fn render(&self, code: &str) -> Option<String>;
+
+    /// Render an inline code span that may be marked with a language.
+    ///
+    /// `language` comes from a `{.lang}` attribute written directly after the
+    /// span (`` `let x = 1`{.rust} ``); the attribute is removed from the
+    /// output whenever an inline code handler is set. The default ignores the
+    /// language and calls [`render`](Self::render).
+    fn render_with_language(&self, code: &str, language: Option<&str>) -> Option<String> {
+        let _ = language;
+        self.render(code)
+    }
 }
 
 /// Type alias for a boxed inline code handler.
@@ -200,6 +323,53 @@ pub trait WikiLinkResolver: Send + Sync {
 /// Type alias for a boxed wiki link resolver.
 pub type BoxedWikiLinkResolver = Arc<dyn WikiLinkResolver>;
 
+/// Markdown loaded for an include directive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncludedFile {
+    /// Resolved path of the included file.
+    ///
+    /// Used for cycle detection, as the `source_path` for nested includes,
+    /// and as the file recorded in source-map entries.
+    pub path: String,
+    /// The file's markdown content. Any frontmatter is ignored.
+    pub content: String,
+}
+
+impl IncludedFile {
+    /// Create an included file from its resolved path and content.
+    pub fn new(path: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            content: content.into(),
+        }
+    }
+}
+
+/// A handler for loading the markdown named by include directives.
+///
+/// Directives are written on their own line, as `<!-- include path.md -->`
+/// or `{{#include path.md}}`, optionally followed by `shift=N` to move the
+/// included headings N levels deeper (or shallower, for negative N).
+pub trait IncludeResolver: Send + Sync {
+    /// Load an included file.
+    ///
+    /// # Arguments
+    /// * `path` - The path as written in the directive (e.g., `shared/license.md`)
+    /// * `source_path` - The path of the file containing the directive
+    ///
+    /// # Returns
+    /// The resolved file, or an error (typically [`Error::Include`](crate::Error::Include))
+    /// that aborts rendering.
+    fn resolve<'a>(
+        &'a self,
+        path: &'a str,
+        source_path: Option<&'a str>,
+    ) -> Pin<Box<dyn Future<Output = Result<IncludedFile>> + Send + 'a>>;
+}
+
+/// Type alias for a boxed include resolver.
+pub type BoxedIncludeResolver = Arc<dyn IncludeResolver>;
+
 /// Default req handler that renders simple anchor divs.
 ///
 /// This is used when no custom req handler is registered.
@@ -229,7 +399,45 @@ impl ReqHandler for DefaultReqHandler {
 /// A simple handler that wraps code in `<div class=\"code-block\"><pre><code>` tags without processing.
 ///
 /// This is used as a fallback when no handler is registered for a language.
-pub struct RawCodeHandler;
+/// A `title="…"` or `file="…"` fence attribute is shown in a header above the code.
+#[derive(Debug, Clone, Default)]
+pub struct RawCodeHandler {
+    /// Whether to add a copy-to-clipboard button
+    copy_button: bool,
+}
+
+impl RawCodeHandler {
+    /// Create a new RawCodeHandler.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enable or disable the copy-to-clipboard button.
+    ///
+    /// The button's script is requested once per page through a [`HeadInjection`].
+    pub fn with_copy_button(mut self, enabled: bool) -> Self {
+        self.copy_button = enabled;
+        self
+    }
+
+    fn render_block(&self, info: &CodeBlockInfo, code: &str) -> CodeBlockOutput {
+        let escaped = html_escape(code);
+        let lang_class = if info.language.is_empty() {
+            String::new()
+        } else {
+            format!(" class=\"language-{}\"", html_escape(&info.language))
+        };
+        let header = code_header(info.title(), None, self.copy_button);
+        CodeBlockOutput {
+            html: format!(
+                "<div class=\"code-block\">{}<pre><code{}>{}</code></pre></div>",
+                header, lang_class, escaped
+            ),
+            head_injections: copy_button_injections(self.copy_button),
+            assets: Vec::new(),
+        }
+    }
+}
 
 impl CodeBlockHandler for RawCodeHandler {
     fn render<'a>(
@@ -238,19 +446,169 @@ impl CodeBlockHandler for RawCodeHandler {
         code: &'a str,
     ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
         Box::pin(async move {
-            let escaped = html_escape(code);
-            let lang_class = if language.is_empty() {
-                String::new()
-            } else {
-                format!(" class=\"language-{}\"", html_escape(language))
-            };
-            Ok(format!(
-                "<div class=\"code-block\"><pre><code{}>{}</code></pre></div>",
-                lang_class, escaped
-            )
-            .into())
+            let info = CodeBlockInfo::from_language(language);
+            Ok(self.render_block(&info, code))
         })
     }
+
+    fn render_with_info<'a>(
+        &'a self,
+        info: &'a CodeBlockInfo,
+        code: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
+        Box::pin(async move { Ok(self.render_block(info, code)) })
+    }
+}
+
+/// Script behind the copy buttons emitted by [`code_header`].
+const COPY_BUTTON_SCRIPT: &str = r#"<script>
+document.addEventListener("click", (event) => {
+  const button = event.target.closest(".code-copy");
+  const code = button?.closest(".code-block")?.querySelector("pre code");
+  if (!code) return;
+  navigator.clipboard.writeText(code.innerText).then(() => {
+    button.classList.add("copied");
+    setTimeout(() => button.classList.remove("copied"), 1500);
+  });
+});
+</script>"#;
+
+/// Header shown above a code block, with an optional title, language label
+/// and copy button. Empty when there is nothing to show.
+pub(crate) fn code_header(
+    title: Option<&str>,
+    language: Option<&str>,
+    copy_button: bool,
+) -> String {
+    if title.is_none() && !copy_button {
+        return language
+            .map(|language| format!("<div class=\"code-header\">{}</div>", html_escape(language)))
+            .unwrap_or_default();
+    }
+
+    let mut header = String::from("<div class=\"code-header\">");
+    if let Some(title) = title {
+        header.push_str(&format!(
+            "<span class=\"code-title\">{}</span>",
+            html_escape(title)
+        ));
+    }
+    if let Some(language) = language {
+        header.push_str(&format!(
+            "<span class=\"code-lang\">{}</span>",
+            html_escape(language)
+        ));
+    }
+    if copy_button {
+        header.push_str(
+            "<button class=\"code-copy\" type=\"button\" aria-label=\"Copy code\">Copy</button>",
+        );
+    }
+    header.push_str("</div>");
+    header
+}
+
+/// The head injection for copy buttons, if enabled.
+pub(crate) fn copy_button_injections(copy_button: bool) -> Vec<HeadInjection> {
+    if !copy_button {
+        return Vec::new();
+    }
+    vec![HeadInjection {
+        key: "code-copy".to_string(),
+        html: COPY_BUTTON_SCRIPT.to_string(),
+    }]
+}
+
+/// Script behind tabbed widgets (see [`tab_list`]). Picking a tab switches
+/// every widget of the same kind on the page that has a tab with that label,
+/// and the choice is remembered per kind across pages.
+const TABS_SCRIPT: &str = r##"<script>
+(() => {
+  const key = (kind) => `marq-${kind}-tab`;
+  const tabsOf = (widget) => widget.querySelectorAll(':scope > [role="tablist"] > [role="tab"]');
+
+  function show(widget, tab) {
+    tabsOf(widget).forEach((t) => t.setAttribute('aria-selected', String(t.dataset.tab === tab)));
+    widget.querySelectorAll(':scope > [role="tabpanel"]').forEach((p) => {
+      p.hidden = p.dataset.tab !== tab;
+    });
+  }
+
+  function init() {
+    document.querySelectorAll('[data-tabs]').forEach((widget) => {
+      let stored = null;
+      try { stored = localStorage.getItem(key(widget.dataset.tabs)); } catch (_) {}
+      const tabs = [...tabsOf(widget)];
+      const tab = tabs.find((t) => t.dataset.tab === stored) || tabs[0];
+      if (tab) show(widget, tab.dataset.tab);
+    });
+  }
+
+  document.addEventListener('click', (e) => {
+    const button = e.target.closest('[data-tabs] > [role="tablist"] > [role="tab"]');
+    if (!button) return;
+    const kind = button.parentElement.parentElement.dataset.tabs;
+    const tab = button.dataset.tab;
+    document.querySelectorAll('[data-tabs]').forEach((widget) => {
+      if (widget.dataset.tabs !== kind) return;
+      if ([...tabsOf(widget)].some((t) => t.dataset.tab === tab)) show(widget, tab);
+    });
+    try { localStorage.setItem(key(kind), tab); } catch (_) {}
+  });
+
+  if (document.readyState === 'loading') {
+    document.addEventListener('DOMContentLoaded', init);
+  } else {
+    init();
+  }
+})();
+</script>"##;
+
+/// Tab buttons for a tabbed widget, given `(key, label)` pairs, with the
+/// first tab selected.
+///
+/// The widget's root element carries `data-tabs="{kind}"`, and each panel is
+/// a direct child with `role="tabpanel"` and the `data-tab` of its key.
+/// Panels are not hidden in the markup, so they stay readable without JS.
+pub(crate) fn tab_list<'l>(
+    kind: &str,
+    tabs: impl IntoIterator<Item = (&'l str, &'l str)>,
+) -> String {
+    let mut html = format!("<div class=\"{kind}-tablist\" role=\"tablist\">");
+    for (index, (key, label)) in tabs.into_iter().enumerate() {
+        html.push_str(&format!(
+            "<button class=\"{kind}-tab\" type=\"button\" role=\"tab\" aria-selected=\"{}\" data-tab=\"{}\">{}</button>",
+            index == 0,
+            html_escape(key),
+            html_escape(label)
+        ));
+    }
+    html.push_str("</div>");
+    html
+}
+
+/// `data-tab` keys for tabs with these labels: the label itself, with `-2`,
+/// `-3`… appended to repeated labels so every tab of a widget can be selected.
+pub(crate) fn tab_keys<'l>(labels: impl IntoIterator<Item = &'l str>) -> Vec<String> {
+    let mut keys: Vec<String> = Vec::new();
+    for label in labels {
+        let mut key = label.to_string();
+        let mut n = 1;
+        while keys.contains(&key) {
+            n += 1;
+            key = format!("{label}-{n}");
+        }
+        keys.push(key);
+    }
+    keys
+}
+
+/// The head injection that makes widgets built with [`tab_list`] switch.
+pub(crate) fn tabs_injection() -> HeadInjection {
+    HeadInjection {
+        key: "tabs".to_string(),
+        html: TABS_SCRIPT.to_string(),
+    }
 }
 
 /// Escape HTML special characters.
@@ -283,7 +641,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_raw_code_handler() {
-        let handler = RawCodeHandler;
+        let handler = RawCodeHandler::new();
         let output = handler.render("rust", "fn main() {}").await.unwrap();
         assert_eq!(
             output.html,
@@ -292,9 +650,24 @@ mod tests {
         assert!(output.head_injections.is_empty());
     }
 
+    #[tokio::test]
+    async fn test_raw_code_handler_title_and_copy_button() {
+        let handler = RawCodeHandler::new().with_copy_button(true);
+        let info = CodeBlockInfo::parse("rust title=\"main.rs\"");
+        let output = handler
+            .render_with_info(&info, "fn main() {}")
+            .await
+            .unwrap();
+        assert_eq!(
+            output.html,
+            "<div class=\"code-block\"><div class=\"code-header\"><span class=\"code-title\">main.rs</span><button class=\"code-copy\" type=\"button\" aria-label=\"Copy code\">Copy</button></div><pre><code class=\"language-rust\">fn main() {}</code></pre></div>"
+        );
+        assert_eq!(output.head_injections.len(), 1);
+    }
+
     #[tokio::test]
     async fn test_raw_code_handler_escapes_html() {
-        let handler = RawCodeHandler;
+        let handler = RawCodeHandler::new();
         let output = handler.render("html", "<div>test</div>").await.unwrap();
         assert!(output.html.contains("&lt;div&gt;"));
         assert!(output.head_injections.is_empty());
@@ -4,10 +4,13 @@
 //! - `highlight` - Syntax highlighting via arborium
 //! - `aasvg` - ASCII art to SVG conversion
 //! - `pikru` - Pikchr diagram rendering
+//! - `dot` - Graphviz DOT diagram rendering
 //!
 //! The following handlers are always available:
 //! - `TermHandler` - Terminal output passthrough
+//! - `AnsiHandler` - Raw ANSI terminal output converted to `<t-*>` HTML
 //! - `MermaidHandler` - Client-side Mermaid.js diagrams
+//! - `CsvHandler` - CSV/TSV data rendered as tables
 
 use std::future::Future;
 use std::pin::Pin;
@@ -15,26 +18,158 @@ use std::pin::Pin;
 use std::sync::Arc;
 
 use crate::Result;
-use crate::handler::{CodeBlockHandler, CodeBlockOutput};
+use crate::fence::CodeBlockInfo;
+use crate::handler::{CodeBlockHandler, CodeBlockOutput, StaticAsset};
+
+use crate::handler::CodeBlockContext;
 
 #[cfg(feature = "highlight")]
 use arborium::advanced::{CompiledGrammar, GrammarConfig, ParseContext, spans_to_html};
 
+/// Language aliases every highlighter starts with.
 #[cfg(feature = "highlight")]
-fn normalize_arborium_language(language: &str) -> &str {
-    match language {
-        "jinja" => "jinja2",
-        "vx" => "vixen",
-        _ => language,
-    }
-}
-
+const BUILTIN_ALIASES: &[(&str, &str)] = &[
+    ("console", "bash"),
+    ("jinja", "jinja2"),
+    ("js", "javascript"),
+    ("py", "python"),
+    ("rs", "rust"),
+    ("sh", "bash"),
+    ("shell", "bash"),
+    ("shell-session", "bash"),
+    ("ts", "typescript"),
+    ("vx", "vixen"),
+    ("yml", "yaml"),
+];
+
+/// Grammars compiled in through the `lang-*` features.
+#[cfg(feature = "highlight")]
+const BUILTIN_LANGUAGES: &[&str] = &[
+    #[cfg(feature = "lang-asm")]
+    "asm",
+    #[cfg(feature = "lang-bash")]
+    "bash",
+    #[cfg(feature = "lang-c")]
+    "c",
+    #[cfg(feature = "lang-capnp")]
+    "capnp",
+    #[cfg(feature = "lang-clojure")]
+    "clojure",
+    #[cfg(feature = "lang-cmake")]
+    "cmake",
+    #[cfg(feature = "lang-cpp")]
+    "cpp",
+    #[cfg(feature = "lang-css")]
+    "css",
+    #[cfg(feature = "lang-diff")]
+    "diff",
+    #[cfg(feature = "lang-dockerfile")]
+    "dockerfile",
+    #[cfg(feature = "lang-elixir")]
+    "elixir",
+    #[cfg(feature = "lang-fish")]
+    "fish",
+    #[cfg(feature = "lang-go")]
+    "go",
+    #[cfg(feature = "lang-graphql")]
+    "graphql",
+    #[cfg(feature = "lang-haskell")]
+    "haskell",
+    #[cfg(feature = "lang-hcl")]
+    "hcl",
+    #[cfg(feature = "lang-html")]
+    "html",
+    #[cfg(feature = "lang-ini")]
+    "ini",
+    #[cfg(feature = "lang-java")]
+    "java",
+    #[cfg(feature = "lang-javascript")]
+    "javascript",
+    #[cfg(feature = "lang-jinja2")]
+    "jinja2",
+    #[cfg(feature = "lang-json")]
+    "json",
+    #[cfg(feature = "lang-julia")]
+    "julia",
+    #[cfg(feature = "lang-kotlin")]
+    "kotlin",
+    #[cfg(feature = "lang-lua")]
+    "lua",
+    #[cfg(feature = "lang-markdown")]
+    "markdown",
+    #[cfg(feature = "lang-meson")]
+    "meson",
+    #[cfg(feature = "lang-nginx")]
+    "nginx",
+    #[cfg(feature = "lang-nix")]
+    "nix",
+    #[cfg(feature = "lang-ocaml")]
+    "ocaml",
+    #[cfg(feature = "lang-perl")]
+    "perl",
+    #[cfg(feature = "lang-php")]
+    "php",
+    #[cfg(feature = "lang-powershell")]
+    "powershell",
+    #[cfg(feature = "lang-python")]
+    "python",
+    #[cfg(feature = "lang-r")]
+    "r",
+    #[cfg(feature = "lang-ruby")]
+    "ruby",
+    #[cfg(feature = "lang-rust")]
+    "rust",
+    #[cfg(feature = "lang-scala")]
+    "scala",
+    #[cfg(feature = "lang-scss")]
+    "scss",
+    #[cfg(feature = "lang-sql")]
+    "sql",
+    #[cfg(feature = "lang-styx")]
+    "styx",
+    #[cfg(feature = "lang-svelte")]
+    "svelte",
+    #[cfg(feature = "lang-swift")]
+    "swift",
+    #[cfg(feature = "lang-textproto")]
+    "textproto",
+    #[cfg(feature = "lang-thrift")]
+    "thrift",
+    #[cfg(feature = "lang-toml")]
+    "toml",
+    #[cfg(feature = "lang-tsx")]
+    "tsx",
+    #[cfg(feature = "lang-typescript")]
+    "typescript",
+    #[cfg(feature = "lang-typst")]
+    "typst",
+    #[cfg(feature = "lang-vim")]
+    "vim",
+    #[cfg(feature = "lang-vue")]
+    "vue",
+    #[cfg(feature = "lang-xml")]
+    "xml",
+    #[cfg(feature = "lang-yaml")]
+    "yaml",
+    #[cfg(feature = "lang-zig")]
+    "zig",
+    #[cfg(feature = "lang-zsh")]
+    "zsh",
+];
+
+/// Grammars and highlighting config, shared by every render.
+///
+/// Parse contexts hold mutable parser state, so each highlight call checks
+/// one out of a pool rather than locking a single parser for the whole parse.
+/// The pool grows to the number of concurrent highlights and never shrinks.
 #[cfg(feature = "highlight")]
 struct ArboriumEngine {
     store: Arc<arborium::GrammarStore>,
-    ctx: Option<ParseContext>,
     config: arborium::Config,
     third_party_grammars: std::collections::HashMap<String, Arc<CompiledGrammar>>,
+    /// Alternative names, mapped to the grammar name they stand for
+    aliases: std::collections::HashMap<String, String>,
+    contexts: std::sync::Mutex<Vec<ParseContext>>,
 }
 
 #[cfg(feature = "highlight")]
@@ -46,9 +181,13 @@ impl ArboriumEngine {
     fn with_config(config: arborium::Config) -> Self {
         let mut engine = Self {
             store: Arc::new(arborium::GrammarStore::new()),
-            ctx: None,
             config,
             third_party_grammars: std::collections::HashMap::new(),
+            aliases: BUILTIN_ALIASES
+                .iter()
+                .map(|(alias, language)| (alias.to_string(), language.to_string()))
+                .collect(),
+            contexts: std::sync::Mutex::new(Vec::new()),
         };
         engine.register_feature_languages();
         engine
@@ -56,13 +195,37 @@ impl ArboriumEngine {
 
     fn add_third_party_language(&mut self, languages: &[&str], grammar: Arc<CompiledGrammar>) {
         for language in languages {
-            self.third_party_grammars.insert(
-                normalize_arborium_language(language).to_string(),
-                grammar.clone(),
-            );
+            self.third_party_grammars
+                .insert(language.to_string(), grammar.clone());
         }
     }
 
+    fn add_alias(&mut self, alias: &str, language: &str) {
+        self.aliases.insert(alias.to_string(), language.to_string());
+    }
+
+    /// The grammar name for `language`, following aliases.
+    fn resolve_alias<'l>(&'l self, language: &'l str) -> &'l str {
+        self.aliases.get(language).map_or(language, String::as_str)
+    }
+
+    /// Grammar names and aliases that will highlight, sorted.
+    fn supported_languages(&self) -> Vec<String> {
+        let mut languages: std::collections::BTreeSet<String> = BUILTIN_LANGUAGES
+            .iter()
+            .map(|language| language.to_string())
+            .chain(self.third_party_grammars.keys().cloned())
+            .collect();
+        let aliases: Vec<String> = self
+            .aliases
+            .iter()
+            .filter(|(_, language)| languages.contains(*language))
+            .map(|(alias, _)| alias.clone())
+            .collect();
+        languages.extend(aliases);
+        languages.into_iter().collect()
+    }
+
     fn add_tree_sitter_language(
         &mut self,
         languages: &[&str],
@@ -93,7 +256,7 @@ impl ArboriumEngine {
     }
 
     fn highlight(
-        &mut self,
+        &self,
         language: &str,
         code: &str,
     ) -> std::result::Result<String, arborium::Error> {
@@ -102,19 +265,30 @@ impl ArboriumEngine {
     }
 
     fn highlight_spans(
-        &mut self,
+        &self,
         language: &str,
         source: &str,
     ) -> std::result::Result<Vec<arborium::advanced::Span>, arborium::Error> {
-        let language = normalize_arborium_language(language);
+        let language = self.resolve_alias(language);
         let grammar =
             self.grammar(language)
                 .ok_or_else(|| arborium::Error::UnsupportedLanguage {
                     language: language.to_string(),
                 })?;
 
-        self.ensure_context(&grammar)?;
-        let ctx = self.ctx.as_mut().unwrap();
+        let mut ctx = self.checkout_context(&grammar)?;
+        let spans = self.parse_with(&mut ctx, &grammar, language, source);
+        self.contexts.lock().unwrap().push(ctx);
+        spans
+    }
+
+    fn parse_with(
+        &self,
+        ctx: &mut ParseContext,
+        grammar: &CompiledGrammar,
+        language: &str,
+        source: &str,
+    ) -> std::result::Result<Vec<arborium::advanced::Span>, arborium::Error> {
         ctx.set_language(grammar.language())
             .map_err(|_| arborium::Error::ParseError {
                 language: language.to_string(),
@@ -126,6 +300,7 @@ impl ArboriumEngine {
 
         if self.config.max_injection_depth > 0 {
             self.process_injections(
+                ctx,
                 source,
                 result.injections,
                 0,
@@ -138,29 +313,31 @@ impl ArboriumEngine {
     }
 
     fn grammar(&self, language: &str) -> Option<Arc<CompiledGrammar>> {
+        let language = self.resolve_alias(language);
         self.third_party_grammars
             .get(language)
             .cloned()
             .or_else(|| self.store.get(language))
     }
 
-    fn ensure_context(
-        &mut self,
+    /// Take a parse context from the pool, creating one if all are in use.
+    fn checkout_context(
+        &self,
         grammar: &CompiledGrammar,
-    ) -> std::result::Result<(), arborium::Error> {
-        if self.ctx.is_none() {
-            self.ctx = Some(ParseContext::for_grammar(grammar).map_err(|e| {
-                arborium::Error::ParseError {
-                    language: String::new(),
-                    message: e.to_string(),
-                }
-            })?);
+    ) -> std::result::Result<ParseContext, arborium::Error> {
+        let pooled = self.contexts.lock().unwrap().pop();
+        match pooled {
+            Some(ctx) => Ok(ctx),
+            None => ParseContext::for_grammar(grammar).map_err(|e| arborium::Error::ParseError {
+                language: String::new(),
+                message: e.to_string(),
+            }),
         }
-        Ok(())
     }
 
     fn process_injections(
-        &mut self,
+        &self,
+        ctx: &mut ParseContext,
         source: &str,
         injections: Vec<arborium::advanced::Injection>,
         base_offset: u32,
@@ -184,7 +361,6 @@ impl ArboriumEngine {
                 continue;
             };
 
-            let ctx = self.ctx.as_mut().unwrap();
             if ctx.set_language(grammar.language()).is_err() {
                 continue;
             }
@@ -197,6 +373,7 @@ impl ArboriumEngine {
             }));
 
             self.process_injections(
+                ctx,
                 injected_source,
                 result.injections,
                 base_offset + injection.start,
@@ -214,9 +391,13 @@ impl ArboriumEngine {
 /// Requires the `highlight` feature.
 #[cfg(feature = "highlight")]
 pub struct ArboriumHandler {
-    highlighter: std::sync::Mutex<ArboriumEngine>,
+    highlighter: ArboriumEngine,
     /// Whether to show a language header above code blocks
     show_language_header: bool,
+    /// Whether to number lines in every block, not just `linenos` ones
+    line_numbers: bool,
+    /// Whether to add a copy-to-clipboard button to the header
+    copy_button: bool,
 }
 
 #[cfg(feature = "highlight")]
@@ -224,16 +405,20 @@ impl ArboriumHandler {
     /// Create a new ArboriumHandler with default config.
     pub fn new() -> Self {
         Self {
-            highlighter: std::sync::Mutex::new(ArboriumEngine::new()),
+            highlighter: ArboriumEngine::new(),
             show_language_header: true,
+            line_numbers: false,
+            copy_button: false,
         }
     }
 
     /// Create a new ArboriumHandler with custom config.
     pub fn with_config(config: arborium::Config) -> Self {
         Self {
-            highlighter: std::sync::Mutex::new(ArboriumEngine::with_config(config)),
+            highlighter: ArboriumEngine::with_config(config),
             show_language_header: true,
+            line_numbers: false,
+            copy_button: false,
         }
     }
 
@@ -246,32 +431,68 @@ impl ArboriumHandler {
         self
     }
 
+    /// Enable or disable the copy-to-clipboard button in the code header.
+    ///
+    /// The button's script is requested once per page through a
+    /// [`HeadInjection`](crate::HeadInjection).
+    pub fn with_copy_button(mut self, enabled: bool) -> Self {
+        self.copy_button = enabled;
+        self
+    }
+
+    /// Enable or disable line numbers for all code blocks.
+    ///
+    /// Individual blocks can opt in with the `linenos` fence flag, or
+    /// `linenostart=N` to start counting at N.
+    pub fn with_line_numbers(mut self, enabled: bool) -> Self {
+        self.line_numbers = enabled;
+        self
+    }
+
+    /// Use a shared grammar store, e.g. the one a [`CompareHandler`] also uses.
+    pub fn with_grammar_store(mut self, store: Arc<arborium::GrammarStore>) -> Self {
+        self.highlighter.store = store;
+        self
+    }
+
+    /// Highlight fences marked `alias` with the grammar for `language`.
+    ///
+    /// Common aliases such as `sh`, `yml`, `rs`, `js` and `console` are built in.
+    pub fn with_language_alias(mut self, alias: &str, language: &str) -> Self {
+        self.highlighter.add_alias(alias, language);
+        self
+    }
+
+    /// Whether code in `language` (a grammar name or alias) will be highlighted.
+    pub fn supports_language(&self, language: &str) -> bool {
+        self.highlighter.grammar(language).is_some()
+    }
+
+    /// All grammar names and aliases this handler can highlight, sorted.
+    pub fn supported_languages(&self) -> Vec<String> {
+        self.highlighter.supported_languages()
+    }
+
     /// Register a compiled third-party grammar under one or more language names.
     pub fn with_third_party_language(
-        self,
+        mut self,
         languages: &[&str],
         grammar: Arc<CompiledGrammar>,
     ) -> Self {
-        let mut highlighter = self.highlighter.into_inner().unwrap();
-        highlighter.add_third_party_language(languages, grammar);
-        Self {
-            highlighter: std::sync::Mutex::new(highlighter),
-            show_language_header: self.show_language_header,
-        }
+        self.highlighter
+            .add_third_party_language(languages, grammar);
+        self
     }
 
     /// Compile and register a third-party tree-sitter grammar.
     pub fn with_tree_sitter_language(
-        self,
+        mut self,
         languages: &[&str],
         config: GrammarConfig<'static>,
     ) -> std::result::Result<Self, arborium::advanced::GrammarError> {
-        let mut highlighter = self.highlighter.into_inner().unwrap();
-        highlighter.add_tree_sitter_language(languages, config)?;
-        Ok(Self {
-            highlighter: std::sync::Mutex::new(highlighter),
-            show_language_header: self.show_language_header,
-        })
+        self.highlighter
+            .add_tree_sitter_language(languages, config)?;
+        Ok(self)
     }
 
     #[cfg(feature = "lang-vixen")]
@@ -289,6 +510,146 @@ impl Default for ArboriumHandler {
     }
 }
 
+#[cfg(feature = "highlight")]
+impl ArboriumHandler {
+    fn render_block(&self, info: &CodeBlockInfo, code: &str) -> CodeBlockOutput {
+        use crate::handler::{code_header, copy_button_injections, html_escape};
+
+        let language = info.language.as_str();
+        // An unparsable `linenostart` counts from 1 if line numbers are on anyway
+        let line_start = info
+            .attr("linenostart")
+            .and_then(|start| start.parse().ok())
+            .or_else(|| (self.line_numbers || info.has_flag("linenos")).then_some(1));
+        let class = if line_start.is_some() {
+            "code-block line-numbers"
+        } else {
+            "code-block"
+        };
+
+        // Empty language means no syntax highlighting requested - render as plain
+        if language.is_empty() {
+            let code_html = wrap_lines(&html_escape(code), info, line_start);
+            let header = code_header(info.title(), None, self.copy_button);
+            return CodeBlockOutput {
+                html: format!(
+                    "<div class=\"{class}\">{header}<pre><code>{code_html}</code></pre></div>"
+                ),
+                head_injections: copy_button_injections(self.copy_button),
+                assets: Vec::new(),
+            };
+        }
+
+        let escaped_lang = html_escape(language);
+
+        // `diff-rust` or `diff,lang=rust` highlights the code as Rust and
+        // styles the diff markers on top
+        let diff_language = language
+            .strip_prefix("diff-")
+            .or_else(|| info.attr("lang").filter(|_| language == "diff"));
+        let class = match diff_language {
+            Some(_) => format!("{class} diff"),
+            None => class.to_string(),
+        };
+
+        let highlighted_code = match diff_language {
+            Some(diff_language) => self.highlight_diff(diff_language, code),
+            None => self.highlight(language, code),
+        };
+        let highlighted_code = wrap_lines(&highlighted_code, info, line_start);
+
+        let header = code_header(
+            info.title(),
+            self.show_language_header.then_some(language),
+            self.copy_button,
+        );
+
+        // Build the output with data-lang for CSS targeting
+        CodeBlockOutput {
+            html: format!(
+                "<div class=\"{class}\" data-lang=\"{escaped_lang}\">{header}<pre><code class=\"language-{escaped_lang}\">{highlighted_code}</code></pre></div>"
+            ),
+            head_injections: copy_button_injections(self.copy_button),
+            assets: Vec::new(),
+        }
+    }
+
+    /// Highlight with arborium, falling back to escaped text for unsupported languages.
+    fn highlight(&self, language: &str, code: &str) -> String {
+        match self.highlighter.highlight(language, code) {
+            Ok(html) => {
+                // Trim trailing newline from arborium output
+                // See: https://github.com/bearcove/arborium/issues/128
+                html.trim_end_matches('\n').to_string()
+            }
+            Err(_e) => crate::handler::html_escape(code),
+        }
+    }
+
+    /// Highlight a unified diff of `language` code.
+    ///
+    /// The code lines are highlighted together with their markers removed, so
+    /// constructs spanning several lines still highlight correctly. Each line
+    /// is then wrapped in a `diff-line` span classed `diff-add`, `diff-remove`,
+    /// `diff-context` or `diff-meta` (file and hunk headers).
+    fn highlight_diff(&self, language: &str, code: &str) -> String {
+        use crate::handler::html_escape;
+
+        let mut lines = Vec::new();
+        let mut source = Vec::new();
+        let mut in_hunk = false;
+        for line in code.lines() {
+            let is_meta = line.starts_with("@@")
+                || (!in_hunk
+                    && ["diff ", "index ", "--- ", "+++ "]
+                        .iter()
+                        .any(|prefix| line.starts_with(prefix)));
+            in_hunk |= line.starts_with("@@");
+            if is_meta {
+                lines.push(("diff-meta", None));
+                source.push(line);
+                continue;
+            }
+
+            in_hunk = true;
+            let (kind, marker) = match line.chars().next() {
+                Some('+') => ("diff-add", "+"),
+                Some('-') => ("diff-remove", "-"),
+                Some(' ') => ("diff-context", " "),
+                _ => ("diff-context", ""),
+            };
+            lines.push((kind, Some(marker)));
+            source.push(&line[marker.len()..]);
+        }
+
+        // Only code lines go through the highlighter
+        let code_source = lines
+            .iter()
+            .zip(&source)
+            .filter(|((_, marker), _)| marker.is_some())
+            .map(|(_, line)| *line)
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut highlighted = split_html_lines(&self.highlight(language, &code_source)).into_iter();
+
+        lines
+            .iter()
+            .zip(&source)
+            .map(|((kind, marker), line)| {
+                let content = match marker {
+                    Some(marker) => format!(
+                        "<span class=\"diff-marker\">{marker}</span>{}",
+                        highlighted.next().unwrap_or_else(|| html_escape(line))
+                    ),
+                    None => html_escape(line),
+                };
+                format!("<span class=\"diff-line {kind}\">{content}</span>")
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
 #[cfg(feature = "highlight")]
 impl CodeBlockHandler for ArboriumHandler {
     fn render<'a>(
@@ -297,46 +658,208 @@ impl CodeBlockHandler for ArboriumHandler {
         code: &'a str,
     ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
         Box::pin(async move {
-            use crate::handler::html_escape;
-
-            // Empty language means no syntax highlighting requested - render as plain
-            if language.is_empty() {
-                let escaped = html_escape(code);
-                return Ok(format!(
-                    "<div class=\"code-block\"><pre><code>{escaped}</code></pre></div>"
-                )
-                .into());
-            }
+            let info = CodeBlockInfo::from_language(language);
+            Ok(self.render_block(&info, code))
+        })
+    }
 
-            let escaped_lang = html_escape(language);
+    fn render_with_info<'a>(
+        &'a self,
+        info: &'a CodeBlockInfo,
+        code: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
+        Box::pin(async move { Ok(self.render_block(info, code)) })
+    }
+}
 
-            // Try to highlight with arborium
-            let mut hl = self.highlighter.lock().unwrap();
-            let highlighted_code = match hl.highlight(language, code) {
-                Ok(html) => {
-                    // Trim trailing newline from arborium output
-                    // See: https://github.com/bearcove/arborium/issues/128
-                    html.trim_end_matches('\n').to_string()
-                }
-                Err(_e) => {
-                    // Fall back to plain text rendering for unsupported languages
-                    html_escape(code)
-                }
-            };
+/// Wrap each line of highlighted code in a `<span class="line">`.
+///
+/// Lines in `info.highlight_lines` get an extra `highlighted` class, and with
+/// `line_start` each line carries its number in `data-line`. Without either,
+/// the HTML is returned unchanged.
+#[cfg(feature = "highlight")]
+fn wrap_lines(html: &str, info: &CodeBlockInfo, line_start: Option<usize>) -> String {
+    if line_start.is_none() && info.highlight_lines.is_empty() {
+        return html.to_string();
+    }
 
-            // Build the output with data-lang for CSS targeting
-            if self.show_language_header {
-                Ok(format!(
-                    "<div class=\"code-block\" data-lang=\"{escaped_lang}\"><div class=\"code-header\">{escaped_lang}</div><pre><code class=\"language-{escaped_lang}\">{highlighted_code}</code></pre></div>"
-                )
-                .into())
+    split_html_lines(html)
+        .iter()
+        .enumerate()
+        .map(|(index, line)| {
+            let class = if info.is_line_highlighted(index + 1) {
+                "line highlighted"
             } else {
-                Ok(format!(
-                    "<div class=\"code-block\" data-lang=\"{escaped_lang}\"><pre><code class=\"language-{escaped_lang}\">{highlighted_code}</code></pre></div>"
-                )
-                .into())
+                "line"
+            };
+            match line_start {
+                Some(start) => format!(
+                    "<span class=\"{class}\" data-line=\"{}\">{line}</span>",
+                    start + index
+                ),
+                None => format!("<span class=\"{class}\">{line}</span>"),
             }
         })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Split HTML at newlines, closing elements that are open at the end of a line
+/// and reopening them at the start of the next, so every line is well-formed.
+#[cfg(feature = "highlight")]
+fn split_html_lines(html: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    // Opening tags (verbatim) and element names of the currently open elements
+    let mut open: Vec<(&str, &str)> = Vec::new();
+    let mut rest = html;
+
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            let end = rest.find('>').map_or(rest.len(), |i| i + 1);
+            let tag = &rest[..end];
+            if let Some(name) = tag.strip_prefix("</") {
+                let name = name.trim_end_matches('>').trim();
+                if let Some(index) = open.iter().rposition(|(_, open_name)| *open_name == name) {
+                    open.truncate(index);
+                }
+            } else if !tag.ends_with("/>") {
+                let name = tag[1..]
+                    .split(|c: char| c.is_whitespace() || c == '>')
+                    .next()
+                    .unwrap_or_default();
+                open.push((tag, name));
+            }
+            current.push_str(tag);
+            rest = &rest[end..];
+        } else if c == '\n' {
+            for (_, name) in open.iter().rev() {
+                current.push_str(&format!("</{name}>"));
+            }
+            lines.push(std::mem::take(&mut current));
+            for (tag, _) in &open {
+                current.push_str(tag);
+            }
+            rest = &rest[1..];
+        } else {
+            current.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    lines.push(current);
+    lines
+}
+
+/// Inline code highlighter using arborium.
+///
+/// Highlights inline code spans that name a language, either with a `{.lang}`
+/// attribute after the span (`` `let x = 1`{.rust} ``) or, when enabled with
+/// [`with_language_prefix`](Self::with_language_prefix), a `lang:` prefix
+/// inside it (`` `rust:let x = 1` ``). The prefix only counts when it names a
+/// known grammar, but ordinary text such as `` `c:\Windows` `` can still look
+/// like one, so it is off by default. Spans without a
+/// language use the default language, if one is set, and are otherwise left
+/// to the default rendering.
+///
+/// Requires the `highlight` feature.
+#[cfg(feature = "highlight")]
+pub struct ArboriumInlineHandler {
+    highlighter: ArboriumEngine,
+    /// Language for spans that don't name one
+    default_language: Option<String>,
+    /// Whether a `lang:` prefix selects the language
+    language_prefix: bool,
+}
+
+#[cfg(feature = "highlight")]
+impl ArboriumInlineHandler {
+    /// Create a new ArboriumInlineHandler with default config.
+    pub fn new() -> Self {
+        Self {
+            highlighter: ArboriumEngine::new(),
+            default_language: None,
+            language_prefix: false,
+        }
+    }
+
+    /// Create a new ArboriumInlineHandler with custom config.
+    pub fn with_config(config: arborium::Config) -> Self {
+        Self {
+            highlighter: ArboriumEngine::with_config(config),
+            ..Self::new()
+        }
+    }
+
+    /// Highlight spans without a language as `language`, e.g. "rust" for API docs.
+    pub fn with_default_language(mut self, language: &str) -> Self {
+        self.default_language = Some(language.to_string());
+        self
+    }
+
+    /// Enable or disable the `lang:` prefix convention (disabled by default).
+    pub fn with_language_prefix(mut self, enabled: bool) -> Self {
+        self.language_prefix = enabled;
+        self
+    }
+
+    /// Use a shared grammar store, e.g. the one an [`ArboriumHandler`] also uses.
+    pub fn with_grammar_store(mut self, store: Arc<arborium::GrammarStore>) -> Self {
+        self.highlighter.store = store;
+        self
+    }
+
+    /// Highlight spans marked `alias` with the grammar for `language`.
+    pub fn with_language_alias(mut self, alias: &str, language: &str) -> Self {
+        self.highlighter.add_alias(alias, language);
+        self
+    }
+
+    /// Split a `lang:` prefix naming a known grammar off the code.
+    fn split_prefix<'c>(&self, code: &'c str) -> Option<(&'c str, &'c str)> {
+        let (language, rest) = code.split_once(':')?;
+        let known = !language.is_empty()
+            && language
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+            && self.highlighter.grammar(language).is_some();
+        known.then(|| (language, rest.trim_start()))
+    }
+}
+
+#[cfg(feature = "highlight")]
+impl Default for ArboriumInlineHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "highlight")]
+impl crate::handler::InlineCodeHandler for ArboriumInlineHandler {
+    fn render(&self, code: &str) -> Option<String> {
+        self.render_with_language(code, None)
+    }
+
+    fn render_with_language(&self, code: &str, language: Option<&str>) -> Option<String> {
+        use crate::handler::html_escape;
+
+        let (language, code) = match language {
+            Some(language) => (language, code),
+            None => match self.split_prefix(code).filter(|_| self.language_prefix) {
+                Some(split) => split,
+                None => (self.default_language.as_deref()?, code),
+            },
+        };
+
+        let highlighted = match self.highlighter.highlight(language, code) {
+            Ok(html) => html.trim_end_matches('\n').to_string(),
+            Err(_) => html_escape(code),
+        };
+        Some(format!(
+            "<code class=\"language-{}\">{}</code>",
+            html_escape(language),
+            highlighted
+        ))
     }
 }
 
@@ -378,6 +901,289 @@ impl CodeBlockHandler for TermHandler {
     }
 }
 
+/// ANSI terminal output handler.
+///
+/// Parses raw SGR escape sequences (as captured from a CLI run with colour
+/// forced on) and renders the text HTML-escaped, styled with the same `<t-*>`
+/// elements [`TermHandler`] passes through:
+///
+/// - `<t-b>` bold, `<t-d>` dim, `<t-i>` italic, `<t-u>` underline
+/// - `<t-f n="0".."15">` / `<t-bg n="…">`: foreground / background from the
+///   16-colour palette, left to the page's CSS so themes can restyle them
+/// - `<t-f style="color: #rrggbb">` / `<t-bg style="background-color: …">`:
+///   256-colour and truecolor values
+///
+/// Other escape sequences (cursor movement, hyperlinks) are dropped.
+pub struct AnsiHandler;
+
+impl AnsiHandler {
+    /// Create a new AnsiHandler.
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Default for AnsiHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CodeBlockHandler for AnsiHandler {
+    fn render<'a>(
+        &'a self,
+        _language: &'a str,
+        code: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
+        Box::pin(async move {
+            Ok(format!(
+                "<div class=\"code-block term-output\"><pre><code>{}</code></pre></div>",
+                ansi_to_html(code)
+            )
+            .into())
+        })
+    }
+}
+
+/// A terminal colour as set by SGR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AnsiColor {
+    /// One of the 16 palette colours
+    Palette(u8),
+    /// An exact colour, from the 256-colour cube or truecolor
+    Rgb(u8, u8, u8),
+}
+
+impl AnsiColor {
+    /// Colour `n` of the 256-colour palette.
+    fn indexed(n: u8) -> Self {
+        match n {
+            0..=15 => Self::Palette(n),
+            16..=231 => {
+                let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+                let n = n - 16;
+                Self::Rgb(level(n / 36), level(n / 6 % 6), level(n % 6))
+            }
+            _ => {
+                let gray = 8 + (n - 232) * 10;
+                Self::Rgb(gray, gray, gray)
+            }
+        }
+    }
+
+    fn open_tag(self, tag: &str, property: &str) -> String {
+        match self {
+            Self::Palette(n) => format!("<{tag} n=\"{n}\">"),
+            Self::Rgb(r, g, b) => {
+                format!("<{tag} style=\"{property}: #{r:02x}{g:02x}{b:02x}\">")
+            }
+        }
+    }
+}
+
+/// The SGR attributes in effect for a run of text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct AnsiStyle {
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    fg: Option<AnsiColor>,
+    bg: Option<AnsiColor>,
+}
+
+impl AnsiStyle {
+    /// Apply the parameters of one `ESC [ … m` sequence.
+    fn apply(&mut self, params: &[u16]) {
+        let mut params = params.iter().copied();
+        while let Some(code) = params.next() {
+            match code {
+                0 => *self = Self::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                22 => (self.bold, self.dim) = (false, false),
+                23 => self.italic = false,
+                24 => self.underline = false,
+                30..=37 => self.fg = Some(AnsiColor::Palette((code - 30) as u8)),
+                90..=97 => self.fg = Some(AnsiColor::Palette((code - 90 + 8) as u8)),
+                39 => self.fg = None,
+                40..=47 => self.bg = Some(AnsiColor::Palette((code - 40) as u8)),
+                100..=107 => self.bg = Some(AnsiColor::Palette((code - 100 + 8) as u8)),
+                49 => self.bg = None,
+                38 | 48 => {
+                    let color = match params.next() {
+                        Some(5) => params.next().map(|n| AnsiColor::indexed(n.min(255) as u8)),
+                        Some(2) => {
+                            let mut channel = || params.next().map(|v| v.min(255) as u8);
+                            match (channel(), channel(), channel()) {
+                                (Some(r), Some(g), Some(b)) => Some(AnsiColor::Rgb(r, g, b)),
+                                _ => None,
+                            }
+                        }
+                        _ => None,
+                    };
+                    if code == 38 {
+                        self.fg = color;
+                    } else {
+                        self.bg = color;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Opening tags for this style, outermost first; closed in reverse.
+    fn open_tags(&self) -> Vec<(&'static str, String)> {
+        let mut tags = Vec::new();
+        if let Some(bg) = self.bg {
+            tags.push(("t-bg", bg.open_tag("t-bg", "background-color")));
+        }
+        if let Some(fg) = self.fg {
+            tags.push(("t-f", fg.open_tag("t-f", "color")));
+        }
+        for (on, tag) in [
+            (self.bold, "t-b"),
+            (self.dim, "t-d"),
+            (self.italic, "t-i"),
+            (self.underline, "t-u"),
+        ] {
+            if on {
+                tags.push((tag, format!("<{tag}>")));
+            }
+        }
+        tags
+    }
+}
+
+/// Convert text containing ANSI escape sequences to escaped `<t-*>` HTML.
+fn ansi_to_html(input: &str) -> String {
+    use crate::handler::html_escape;
+
+    let mut html = String::with_capacity(input.len());
+    let mut style = AnsiStyle::default();
+    let mut run = String::new();
+    let mut run_style = style;
+
+    let flush = |html: &mut String, run: &mut String, run_style: AnsiStyle| {
+        if run.is_empty() {
+            return;
+        }
+        let tags = run_style.open_tags();
+        for (_, open) in &tags {
+            html.push_str(open);
+        }
+        html.push_str(&html_escape(run));
+        for (name, _) in tags.iter().rev() {
+            html.push_str(&format!("</{name}>"));
+        }
+        run.clear();
+    };
+
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            if style != run_style {
+                flush(&mut html, &mut run, run_style);
+                run_style = style;
+            }
+            run.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI: parameters, then a final byte in @..~
+            Some('[') => {
+                let mut params = String::new();
+                let mut last = None;
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        last = Some(c);
+                        break;
+                    }
+                    params.push(c);
+                }
+                if last == Some('m') {
+                    let params: Vec<u16> = params
+                        .split([';', ':'])
+                        .map(|p| p.parse().unwrap_or(0))
+                        .collect();
+                    style.apply(&params);
+                }
+            }
+            // OSC: up to BEL or ESC \
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' {
+                        chars.next_if_eq(&'\\');
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    flush(&mut html, &mut run, run_style);
+
+    html
+}
+
+/// Wrap a diagram's SVG in a `<figure class="diagram diagram-{kind}">`,
+/// applying the fence attributes shared by the diagram handlers:
+///
+/// - `alt="…"` (or `title="…"`): a `<title>` and `aria-label` on the SVG
+/// - `caption="…"`: a `<figcaption>`
+/// - `align=left|center|right`: a `diagram-align-*` class
+/// - `max-width=600` (pixels) or any CSS length such as `40em`
+#[cfg(any(feature = "aasvg", feature = "pikru", feature = "dot"))]
+fn diagram_figure(svg: &str, info: &CodeBlockInfo, kind: &str) -> String {
+    use crate::handler::html_escape;
+
+    let mut svg = svg.trim().to_string();
+    if let Some(alt) = info.attr("alt").or_else(|| info.attr("title")) {
+        let alt = html_escape(alt);
+        if let Some(start) = svg.find("<svg")
+            && let Some(end) = svg[start..].find('>').map(|end| start + end)
+            && !svg[..end].ends_with('/')
+        {
+            svg.insert_str(end + 1, &format!("<title>{alt}</title>"));
+            svg.insert_str(start + 4, &format!(" role=\"img\" aria-label=\"{alt}\""));
+        }
+    }
+
+    let mut class = format!("diagram diagram-{kind}");
+    if let Some(align @ ("left" | "center" | "right")) = info.attr("align") {
+        class.push_str(&format!(" diagram-align-{align}"));
+    }
+    let style = info
+        .attr("max-width")
+        .filter(|width| {
+            !width.is_empty()
+                && width
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '%')
+        })
+        .map(|width| {
+            let unit = if width.chars().all(|c| c.is_ascii_digit()) {
+                "px"
+            } else {
+                ""
+            };
+            format!(" style=\"max-width: {width}{unit}\"")
+        })
+        .unwrap_or_default();
+    let caption = info
+        .attr("caption")
+        .map(|caption| format!("<figcaption>{}</figcaption>", html_escape(caption)))
+        .unwrap_or_default();
+
+    format!("<figure class=\"{class}\"{style}>{svg}{caption}</figure>")
+}
+
 /// ASCII art to SVG handler using aasvg.
 ///
 /// Requires the `aasvg` feature.
@@ -399,17 +1205,29 @@ impl Default for AasvgHandler {
     }
 }
 
+#[cfg(feature = "aasvg")]
+impl AasvgHandler {
+    fn render_diagram(&self, info: &CodeBlockInfo, code: &str) -> Result<CodeBlockOutput> {
+        Ok(diagram_figure(&aasvg::render(code), info, "aasvg").into())
+    }
+}
+
 #[cfg(feature = "aasvg")]
 impl CodeBlockHandler for AasvgHandler {
     fn render<'a>(
         &'a self,
-        _language: &'a str,
+        language: &'a str,
         code: &'a str,
     ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
-        Box::pin(async move {
-            let svg = aasvg::render(code);
-            Ok(svg.into())
-        })
+        Box::pin(async move { self.render_diagram(&CodeBlockInfo::from_language(language), code) })
+    }
+
+    fn render_with_info<'a>(
+        &'a self,
+        info: &'a CodeBlockInfo,
+        code: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
+        Box::pin(async move { self.render_diagram(info, code) })
     }
 }
 
@@ -444,92 +1262,526 @@ impl Default for PikruHandler {
     }
 }
 
+#[cfg(feature = "pikru")]
+impl PikruHandler {
+    fn render_diagram(
+        &self,
+        info: &CodeBlockInfo,
+        code: &str,
+        fence_line: Option<usize>,
+    ) -> Result<CodeBlockOutput> {
+        let error = |stage: &str, e: &dyn std::fmt::Display| {
+            let message = e.to_string();
+            let message = match fence_line {
+                Some(line) => remap_block_position(&message, line),
+                None => message,
+            };
+            crate::Error::CodeBlockHandler {
+                language: "pik".to_string(),
+                message: format!("{stage} error: {message}"),
+            }
+        };
+
+        // Parse the pikchr source
+        let program = pikru::parse::parse(code).map_err(|e| error("parse", &e))?;
+
+        // Expand macros
+        let program = pikru::macros::expand_macros(program).map_err(|e| error("macro", &e))?;
+
+        // Render to SVG
+        let options = pikru::render::RenderOptions {
+            css_variables: self.css_variables,
+            ..Default::default()
+        };
+        let svg = pikru::render::render_with_options(&program, &options)
+            .map_err(|e| error("render", &e))?;
+        Ok(diagram_figure(&svg, info, "pikchr").into())
+    }
+}
+
+/// Rewrite the positions in a pikru (pest) diagnostic, given relative to the
+/// code block, as positions in the markdown file whose fence is on
+/// `fence_line`: both the ` --> L:C` arrow and the line numbers in the
+/// snippet gutter, which is re-padded to fit the new numbers.
+#[cfg(feature = "pikru")]
+fn remap_block_position(message: &str, fence_line: usize) -> String {
+    use regex::{Captures, Regex};
+    use std::sync::OnceLock;
+
+    static ARROW: OnceLock<Regex> = OnceLock::new();
+    static GUTTER: OnceLock<Regex> = OnceLock::new();
+    let arrow = ARROW.get_or_init(|| Regex::new(r"(-->\s*)(\d+)(:\d+)").unwrap());
+    let gutter = GUTTER.get_or_init(|| Regex::new(r"^ *(\d+)? \|").unwrap());
+
+    let remap = |line: &str| fence_line + line.parse::<usize>().unwrap_or(0);
+    let message = arrow.replace_all(message, |caps: &Captures| {
+        format!("{}{}{}", &caps[1], remap(&caps[2]), &caps[3])
+    });
+
+    let width = message
+        .lines()
+        .filter_map(|line| gutter.captures(line)?.get(1))
+        .map(|number| remap(number.as_str()).to_string().len())
+        .max();
+    let Some(width) = width else {
+        return message.into_owned();
+    };
+    message
+        .split('\n')
+        .map(|line| match gutter.captures(line) {
+            Some(caps) => {
+                let number = caps.get(1).map(|n| remap(n.as_str()).to_string());
+                let rest = &line[caps[0].len()..];
+                format!("{:>width$} |{rest}", number.unwrap_or_default())
+            }
+            None => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 #[cfg(feature = "pikru")]
 impl CodeBlockHandler for PikruHandler {
     fn render<'a>(
         &'a self,
-        _language: &'a str,
+        language: &'a str,
         code: &'a str,
     ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
-        Box::pin(async move {
-            // Parse the pikchr source
-            let program = match pikru::parse::parse(code) {
-                Ok(p) => p,
-                Err(e) => {
-                    return Err(crate::Error::CodeBlockHandler {
-                        language: "pik".to_string(),
-                        message: format!("parse error: {}", e),
-                    });
-                }
-            };
+        Box::pin(
+            async move { self.render_diagram(&CodeBlockInfo::from_language(language), code, None) },
+        )
+    }
 
-            // Expand macros
-            let program = match pikru::macros::expand_macros(program) {
-                Ok(p) => p,
-                Err(e) => {
-                    return Err(crate::Error::CodeBlockHandler {
-                        language: "pik".to_string(),
-                        message: format!("macro error: {}", e),
-                    });
-                }
-            };
+    fn render_with_info<'a>(
+        &'a self,
+        info: &'a CodeBlockInfo,
+        code: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
+        Box::pin(async move { self.render_diagram(info, code, None) })
+    }
 
-            // Render to SVG
-            let options = pikru::render::RenderOptions {
-                css_variables: self.css_variables,
-                ..Default::default()
-            };
-            match pikru::render::render_with_options(&program, &options) {
-                Ok(svg) => Ok(svg.into()),
-                Err(e) => Err(crate::Error::CodeBlockHandler {
-                    language: "pik".to_string(),
-                    message: format!("render error: {}", e),
-                }),
+    fn render_with_context<'a>(
+        &'a self,
+        context: &'a CodeBlockContext<'a>,
+        code: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
+        Box::pin(async move { self.render_diagram(context.info, code, Some(context.line)) })
+    }
+}
+
+/// Graphviz DOT diagram handler using layout-rs.
+///
+/// Lays out ```` ```dot ```` / ```` ```graphviz ```` blocks and renders them
+/// to inline SVG, without calling out to the `dot` binary. Requires the `dot`
+/// feature.
+///
+/// With CSS variables enabled, black strokes, text and fills use
+/// `var(--dot-fg, currentColor)` and white fills use
+/// `var(--dot-bg, transparent)`, so diagrams follow the page's color scheme.
+/// Other colors set in the DOT source are kept as written.
+#[cfg(feature = "dot")]
+pub struct DotHandler {
+    /// Whether to use CSS variables for colors (for dark mode support)
+    pub css_variables: bool,
+}
+
+#[cfg(feature = "dot")]
+impl DotHandler {
+    /// Create a new DotHandler.
+    pub fn new() -> Self {
+        Self {
+            css_variables: false,
+        }
+    }
+
+    /// Create a new DotHandler with CSS variable support.
+    pub fn with_css_variables(css_variables: bool) -> Self {
+        Self { css_variables }
+    }
+}
+
+#[cfg(feature = "dot")]
+impl Default for DotHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "dot")]
+impl DotHandler {
+    fn render_diagram(&self, info: &CodeBlockInfo, code: &str) -> Result<CodeBlockOutput> {
+        use layout::backends::svg::SVGWriter;
+        use layout::gv::{DotParser, GraphBuilder};
+
+        let graph = DotParser::new(code)
+            .process()
+            .map_err(|e| crate::Error::CodeBlockHandler {
+                language: "dot".to_string(),
+                message: format!("parse error: {}", e),
+            })?;
+
+        let mut builder = GraphBuilder::new();
+        builder.visit_graph(&graph);
+        let mut visual = builder.get();
+        let mut writer = SVGWriter::new();
+        visual.do_it(false, false, false, &mut writer);
+        let svg = writer.finalize();
+
+        // Drop the XML prolog so the SVG can be inlined
+        let svg = svg.find("<svg").map_or(svg.as_str(), |start| &svg[start..]);
+        let svg = if self.css_variables {
+            dot_css_variables(svg)
+        } else {
+            svg.to_string()
+        };
+        Ok(diagram_figure(&svg, info, "dot").into())
+    }
+}
+
+#[cfg(feature = "dot")]
+impl CodeBlockHandler for DotHandler {
+    fn render<'a>(
+        &'a self,
+        language: &'a str,
+        code: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
+        Box::pin(async move { self.render_diagram(&CodeBlockInfo::from_language(language), code) })
+    }
+
+    fn render_with_info<'a>(
+        &'a self,
+        info: &'a CodeBlockInfo,
+        code: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
+        Box::pin(async move { self.render_diagram(info, code) })
+    }
+}
+
+/// Move black and white `fill`/`stroke` attributes into a `style` attribute
+/// using CSS variables (presentation attributes can't reference variables).
+#[cfg(feature = "dot")]
+fn dot_css_variables(svg: &str) -> String {
+    use regex::{Captures, Regex};
+    use std::sync::OnceLock;
+
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    static PAINT: OnceLock<Regex> = OnceLock::new();
+    static STYLE: OnceLock<Regex> = OnceLock::new();
+    let tag_re = TAG.get_or_init(|| Regex::new(r"<[A-Za-z][^>]*>").unwrap());
+    let paint_re = PAINT.get_or_init(|| Regex::new(r#"\s(fill|stroke)="([^"]*)""#).unwrap());
+    let style_re = STYLE.get_or_init(|| Regex::new(r#"\sstyle="([^"]*)""#).unwrap());
+
+    tag_re
+        .replace_all(svg, |tag: &Captures| {
+            let tag = &tag[0];
+            let mut declarations = Vec::new();
+            let rewritten = paint_re.replace_all(tag, |paint: &Captures| {
+                let value = match paint[2].to_ascii_lowercase().as_str() {
+                    "black" | "#000" | "#000000" => "var(--dot-fg, currentColor)",
+                    "white" | "#fff" | "#ffffff" => "var(--dot-bg, transparent)",
+                    _ => return paint[0].to_string(),
+                };
+                declarations.push(format!("{}: {}", &paint[1], value));
+                String::new()
+            });
+            if declarations.is_empty() {
+                return tag.to_string();
+            }
+            let declarations = declarations.join("; ");
+
+            if let Some(style) = style_re.captures(&rewritten) {
+                let existing = style[1].trim_end().trim_end_matches(';');
+                let merged = format!(" style=\"{existing}; {declarations}\"");
+                style_re.replace(&rewritten, merged.as_str()).into_owned()
+            } else {
+                let end = rewritten.len() - if rewritten.ends_with("/>") { 2 } else { 1 };
+                format!(
+                    "{} style=\"{declarations}\"{}",
+                    &rewritten[..end],
+                    &rewritten[end..]
+                )
             }
         })
-    }
+        .into_owned()
 }
 
-/// Mermaid diagram handler.
+/// CSV/TSV table handler.
 ///
-/// Emits a `<pre class="mermaid">` block for client-side rendering by
-/// Mermaid.js, wrapped in `data-hotmeal-opaque` for live-reload compatibility.
-/// Includes a head injection that loads Mermaid.js from CDN and listens for
-/// `hotmeal:opaque-changed` events to re-render after live-reload patches.
-pub struct MermaidHandler;
+/// Renders ```` ```csv ```` and ```` ```tsv ```` blocks (quoted fields per
+/// RFC 4180) as an HTML table. Fence options:
+///
+/// - `header` / `no-header`: whether the first row is a header. By default it
+///   is when none of its cells are empty or numeric.
+/// - `align=lcr`: one letter per column (`l`, `c`, `r`, or `-` for none).
+///   Columns without a hint are right-aligned when all their values are numeric.
+/// - `caption="…"`: a table caption.
+/// - `delimiter=";"`: a field separator other than `,` (or tab for `tsv`).
+/// - `src="data/table.csv"`: read the data from a file instead of the block
+///   body. Paths are relative to the document's directory, taken from its
+///   source path inside the directory set with
+///   [`with_base_dir`](Self::with_base_dir), and may not leave the base
+///   directory; without a base directory `src=` is an error. The file is read with blocking I/O
+///   on the rendering task, as tables are expected to be small local files.
+pub struct CsvHandler {
+    base_dir: Option<std::path::PathBuf>,
+}
 
-impl MermaidHandler {
-    /// Create a new MermaidHandler.
+impl CsvHandler {
+    /// Create a new CsvHandler.
     pub fn new() -> Self {
-        Self
+        Self { base_dir: None }
+    }
+
+    /// Allow `src=` attributes, resolved inside this directory.
+    ///
+    /// Files are read synchronously while rendering, blocking the calling
+    /// thread.
+    pub fn with_base_dir(mut self, dir: impl Into<std::path::PathBuf>) -> Self {
+        self.base_dir = Some(dir.into());
+        self
+    }
+
+    /// Read `src`, relative to the directory of `source_path` (itself relative
+    /// to the base directory, or absolute inside it).
+    fn load(&self, src: &str, source_path: Option<&str>) -> std::result::Result<String, String> {
+        use std::path::{Component, Path, PathBuf};
+
+        let base_dir = self
+            .base_dir
+            .as_ref()
+            .ok_or_else(|| format!("cannot load '{src}': no base directory configured"))?;
+        let outside = || format!("'{src}' is outside the base directory");
+
+        let source_dir = source_path
+            .and_then(|path| Path::new(path).parent())
+            .unwrap_or(Path::new(""));
+        let source_dir = source_dir.strip_prefix(base_dir).unwrap_or(source_dir);
+        let mut path = PathBuf::new();
+        for component in source_dir.components().chain(Path::new(src).components()) {
+            match component {
+                Component::Normal(part) => path.push(part),
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    if !path.pop() {
+                        return Err(outside());
+                    }
+                }
+                Component::RootDir | Component::Prefix(_) => return Err(outside()),
+            }
+        }
+        std::fs::read_to_string(base_dir.join(path))
+            .map_err(|e| format!("cannot read '{src}': {e}"))
+    }
+
+    fn render_table(
+        &self,
+        info: &CodeBlockInfo,
+        source_path: Option<&str>,
+        code: &str,
+    ) -> Result<CodeBlockOutput> {
+        use crate::handler::html_escape;
+
+        let error = |message: String| crate::Error::CodeBlockHandler {
+            language: info.language.clone(),
+            message,
+        };
+
+        let data = match info.attr("src") {
+            Some(src) => self.load(src, source_path).map_err(error)?,
+            None => code.to_string(),
+        };
+        let delimiter = match info.attr("delimiter") {
+            Some(d) if d.chars().count() == 1 => d.chars().next().unwrap(),
+            Some(d) => return Err(error(format!("delimiter must be one character, got '{d}'"))),
+            None if info.language == "tsv" => '\t',
+            None => ',',
+        };
+        let rows = parse_delimited(&data, delimiter).map_err(error)?;
+
+        let has_header = if info.has_flag("no-header") {
+            false
+        } else if info.has_flag("header") {
+            true
+        } else {
+            rows.len() > 1
+                && rows[0]
+                    .iter()
+                    .all(|cell| !cell.trim().is_empty() && !is_numeric(cell))
+        };
+        let (header, body) = match rows.split_first() {
+            Some((first, rest)) if has_header => (Some(first), rest),
+            _ => (None, rows.as_slice()),
+        };
+
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let hints: Vec<char> = info.attr("align").unwrap_or_default().chars().collect();
+        let alignments: Vec<Option<&str>> = (0..columns)
+            .map(|column| match hints.get(column) {
+                Some('l') => Some("left"),
+                Some('c') => Some("center"),
+                Some('r') => Some("right"),
+                Some(_) => None,
+                None => {
+                    let mut values = body
+                        .iter()
+                        .filter_map(|row| row.get(column))
+                        .filter(|cell| !cell.trim().is_empty())
+                        .peekable();
+                    (values.peek().is_some() && values.all(|cell| is_numeric(cell)))
+                        .then_some("right")
+                }
+            })
+            .collect();
+
+        let row_html = |row: &[String], tag: &str| {
+            let mut html = String::from("<tr>");
+            for (column, alignment) in alignments.iter().enumerate() {
+                let cell = row.get(column).map(String::as_str).unwrap_or_default();
+                let style = alignment
+                    .map(|a| format!(" style=\"text-align: {a}\""))
+                    .unwrap_or_default();
+                html.push_str(&format!(
+                    "<{tag}{style}>{}</{tag}>",
+                    html_escape(cell.trim())
+                ));
+            }
+            html.push_str("</tr>\n");
+            html
+        };
+
+        let mut html = String::from("<div class=\"csv-table\"><table>");
+        if let Some(caption) = info.attr("caption") {
+            html.push_str(&format!("<caption>{}</caption>", html_escape(caption)));
+        }
+        if let Some(header) = header {
+            html.push_str("<thead>");
+            html.push_str(&row_html(header, "th"));
+            html.push_str("</thead>");
+        }
+        html.push_str("<tbody>\n");
+        for row in body {
+            html.push_str(&row_html(row, "td"));
+        }
+        html.push_str("</tbody></table></div>");
+
+        Ok(html.into())
     }
 }
 
-impl Default for MermaidHandler {
+impl Default for CsvHandler {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl CodeBlockHandler for MermaidHandler {
+impl CodeBlockHandler for CsvHandler {
     fn render<'a>(
         &'a self,
-        _language: &'a str,
+        language: &'a str,
         code: &'a str,
     ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
-        Box::pin(async move {
-            use crate::handler::{HeadInjection, html_escape};
+        Box::pin(
+            async move { self.render_table(&CodeBlockInfo::from_language(language), None, code) },
+        )
+    }
 
-            let escaped = html_escape(code);
-            let html = format!(
-                "<div data-hotmeal-opaque=\"mermaid\"><pre class=\"mermaid\">{escaped}</pre></div>"
-            );
+    fn render_with_info<'a>(
+        &'a self,
+        info: &'a CodeBlockInfo,
+        code: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
+        Box::pin(async move { self.render_table(info, None, code) })
+    }
+
+    fn render_with_context<'a>(
+        &'a self,
+        context: &'a CodeBlockContext<'a>,
+        code: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
+        Box::pin(async move { self.render_table(context.info, context.source_path, code) })
+    }
+}
+
+/// Split delimited text into rows of fields, honoring `"quoted"` fields that
+/// may contain delimiters, newlines and `""` escapes. Blank lines are skipped.
+fn parse_delimited(data: &str, delimiter: char) -> std::result::Result<Vec<Vec<String>>, String> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut quoted = false;
+    let mut line = 1;
+    let mut chars = data.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                '\n' => {
+                    line += 1;
+                    field.push(c);
+                }
+                _ => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.trim().is_empty() && !quoted => {
+                field.clear();
+                in_quotes = true;
+                quoted = true;
+            }
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                if !(row.len() == 1 && row[0].trim().is_empty() && !quoted) {
+                    rows.push(std::mem::take(&mut row));
+                }
+                row.clear();
+                quoted = false;
+                line += 1;
+            }
+            c if c == delimiter => {
+                row.push(std::mem::take(&mut field));
+                quoted = false;
+            }
+            _ => field.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err(format!("unterminated quoted field ending on line {line}"));
+    }
+    row.push(field);
+    if !(row.len() == 1 && row[0].trim().is_empty() && !quoted) {
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Whether a cell holds a number such as `42`, `-3.5`, `1,234`, `12%` or `$9.99`.
+fn is_numeric(cell: &str) -> bool {
+    let cell = cell.trim();
+    let cell = cell.strip_prefix(['$', '€', '£']).unwrap_or(cell);
+    let cell = cell.strip_suffix('%').unwrap_or(cell);
+    let digits = cell.replace([',', '_'], "");
+    !digits.is_empty() && digits.parse::<f64>().is_ok_and(f64::is_finite)
+}
+
+/// Default Mermaid.js module, loaded from jsDelivr.
+pub const MERMAID_CDN_URL: &str =
+    "https://cdn.jsdelivr.net/npm/mermaid@11/dist/mermaid.esm.min.mjs";
 
-            let script = r#"<script type="module">
-import mermaid from 'https://cdn.jsdelivr.net/npm/mermaid@11/dist/mermaid.esm.min.mjs';
+/// Mermaid loader script; the `{{…}}` placeholders are filled per handler.
+const MERMAID_SCRIPT: &str = r#"<script type="module">
+import mermaid from '{{MODULE_URL}}';
 
 function mermaidTheme() {
-  return window.matchMedia('(prefers-color-scheme: dark)').matches ? 'dark' : 'default';
+  return window.matchMedia('(prefers-color-scheme: dark)').matches ? '{{DARK_THEME}}' : '{{LIGHT_THEME}}';
 }
 
 async function renderMermaidNode(pre) {
@@ -576,12 +1828,108 @@ document.addEventListener('hotmeal:opaque-changed', async (e) => {
 });
 </script>"#;
 
+/// Mermaid diagram handler.
+///
+/// Emits a `<pre class="mermaid">` block for client-side rendering by
+/// Mermaid.js, wrapped in `data-hotmeal-opaque` for live-reload compatibility.
+/// Includes a head injection that loads Mermaid.js and listens for
+/// `hotmeal:opaque-changed` events to re-render after live-reload patches.
+///
+/// Mermaid.js is imported from [`MERMAID_CDN_URL`] unless
+/// [`with_module_url`](Self::with_module_url) points elsewhere. For offline
+/// docs or a strict CSP, serve the module yourself: pass its files with
+/// [`with_asset`](Self::with_asset) so they end up in
+/// [`Document::assets`](crate::Document::assets), and set the module URL to
+/// where your site writes them.
+///
+/// ```rust,ignore
+/// let handler = MermaidHandler::new()
+///     .with_module_url("/assets/mermaid/mermaid.esm.min.mjs")
+///     .with_asset(StaticAsset::new("mermaid/mermaid.esm.min.mjs", "text/javascript", bytes))
+///     .with_theme("neutral", "dark");
+/// ```
+pub struct MermaidHandler {
+    module_url: String,
+    light_theme: String,
+    dark_theme: String,
+    assets: Vec<StaticAsset>,
+}
+
+impl MermaidHandler {
+    /// Create a new MermaidHandler.
+    pub fn new() -> Self {
+        Self {
+            module_url: MERMAID_CDN_URL.to_string(),
+            light_theme: "default".to_string(),
+            dark_theme: "dark".to_string(),
+            assets: Vec::new(),
+        }
+    }
+
+    /// Import Mermaid.js from this URL instead of the CDN.
+    pub fn with_module_url(mut self, url: impl Into<String>) -> Self {
+        self.module_url = url.into();
+        self
+    }
+
+    /// Set the Mermaid themes used for light and dark color schemes
+    /// (default `"default"` and `"dark"`).
+    pub fn with_theme(mut self, light: impl Into<String>, dark: impl Into<String>) -> Self {
+        self.light_theme = light.into();
+        self.dark_theme = dark.into();
+        self
+    }
+
+    /// Declare a file (e.g. the Mermaid.js module) to be served with pages
+    /// that contain mermaid diagrams.
+    pub fn with_asset(mut self, asset: StaticAsset) -> Self {
+        self.assets.push(asset);
+        self
+    }
+
+    fn script(&self) -> String {
+        MERMAID_SCRIPT
+            .replace("{{MODULE_URL}}", &js_string(&self.module_url))
+            .replace("{{LIGHT_THEME}}", &js_string(&self.light_theme))
+            .replace("{{DARK_THEME}}", &js_string(&self.dark_theme))
+    }
+}
+
+/// Escape text for a single-quoted JavaScript string inside a `<script>`.
+fn js_string(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\'', "\\'")
+        .replace('\n', "\\n")
+        .replace("</", "<\\/")
+}
+
+impl Default for MermaidHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CodeBlockHandler for MermaidHandler {
+    fn render<'a>(
+        &'a self,
+        _language: &'a str,
+        code: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
+        Box::pin(async move {
+            use crate::handler::{HeadInjection, html_escape};
+
+            let escaped = html_escape(code);
+            let html = format!(
+                "<div data-hotmeal-opaque=\"mermaid\"><pre class=\"mermaid\">{escaped}</pre></div>"
+            );
+
             Ok(CodeBlockOutput {
                 html,
                 head_injections: vec![HeadInjection {
                     key: "mermaid".to_string(),
-                    html: script.to_string(),
+                    html: self.script(),
                 }],
+                assets: self.assets.clone(),
             })
         })
     }
@@ -593,10 +1941,20 @@ document.addEventListener('hotmeal:opaque-changed', async (e) => {
 pub struct CompareSection {
     /// Language identifier for syntax highlighting
     pub language: String,
+    /// Label from `/// rust "Before"`, shown instead of the language
+    pub label: Option<String>,
     /// The code content
     pub code: String,
 }
 
+#[cfg(feature = "highlight")]
+impl CompareSection {
+    /// The section's header text: its label, or else its language.
+    pub fn title(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.language)
+    }
+}
+
 /// Side-by-side code comparison handler.
 ///
 /// Parses code blocks with `/// language` separators and renders them
@@ -614,15 +1972,25 @@ pub struct CompareSection {
 /// ````
 ///
 /// The `/// language` lines act as separators, where `language` is the
-/// syntax highlighting language for the following code section.
+/// syntax highlighting language for the following code section. A quoted
+/// label after the language (`/// rust "Before"`) replaces the language in
+/// the section header, so two sections in the same language can be told apart.
 ///
 /// # Output
 ///
 /// Renders as a flex container with each section displayed side-by-side.
-/// Each section has its language as a header and syntax-highlighted code.
+/// Each section has its label or language as a header and syntax-highlighted code.
+///
+/// In tabbed mode (see [`with_tabs`](Self::with_tabs), or a `tabs` flag on a
+/// single block), the headers become tab buttons and one section is shown at
+/// a time. Choosing a tab switches every tabbed block on the page that has a
+/// tab with the same title, and the choice is remembered in `localStorage`.
+/// Without JavaScript all sections stay visible.
 #[cfg(feature = "highlight")]
 pub struct CompareHandler {
-    highlighter: std::sync::Mutex<ArboriumEngine>,
+    highlighter: ArboriumEngine,
+    /// Whether every block renders as tabs
+    tabs: bool,
 }
 
 #[cfg(feature = "highlight")]
@@ -630,41 +1998,69 @@ impl CompareHandler {
     /// Create a new CompareHandler with default config.
     pub fn new() -> Self {
         Self {
-            highlighter: std::sync::Mutex::new(ArboriumEngine::new()),
+            highlighter: ArboriumEngine::new(),
+            tabs: false,
         }
     }
 
     /// Create a new CompareHandler with custom config.
     pub fn with_config(config: arborium::Config) -> Self {
         Self {
-            highlighter: std::sync::Mutex::new(ArboriumEngine::with_config(config)),
+            highlighter: ArboriumEngine::with_config(config),
+            tabs: false,
         }
     }
 
+    /// Render every compare block as tabs instead of side-by-side.
+    ///
+    /// Individual blocks can opt in with the `tabs` fence flag (` ```compare,tabs `).
+    pub fn with_tabs(mut self, enabled: bool) -> Self {
+        self.tabs = enabled;
+        self
+    }
+
+    /// Use a shared grammar store, e.g. the one an [`ArboriumHandler`] also uses.
+    pub fn with_grammar_store(mut self, store: Arc<arborium::GrammarStore>) -> Self {
+        self.highlighter.store = store;
+        self
+    }
+
+    /// Highlight sections marked `alias` with the grammar for `language`.
+    pub fn with_language_alias(mut self, alias: &str, language: &str) -> Self {
+        self.highlighter.add_alias(alias, language);
+        self
+    }
+
+    /// Whether code in `language` (a grammar name or alias) will be highlighted.
+    pub fn supports_language(&self, language: &str) -> bool {
+        self.highlighter.grammar(language).is_some()
+    }
+
+    /// All grammar names and aliases this handler can highlight, sorted.
+    pub fn supported_languages(&self) -> Vec<String> {
+        self.highlighter.supported_languages()
+    }
+
     /// Register a compiled third-party grammar under one or more language names.
     pub fn with_third_party_language(
-        self,
+        mut self,
         languages: &[&str],
         grammar: Arc<CompiledGrammar>,
     ) -> Self {
-        let mut highlighter = self.highlighter.into_inner().unwrap();
-        highlighter.add_third_party_language(languages, grammar);
-        Self {
-            highlighter: std::sync::Mutex::new(highlighter),
-        }
+        self.highlighter
+            .add_third_party_language(languages, grammar);
+        self
     }
 
     /// Compile and register a third-party tree-sitter grammar.
     pub fn with_tree_sitter_language(
-        self,
+        mut self,
         languages: &[&str],
         config: GrammarConfig<'static>,
     ) -> std::result::Result<Self, arborium::advanced::GrammarError> {
-        let mut highlighter = self.highlighter.into_inner().unwrap();
-        highlighter.add_tree_sitter_language(languages, config)?;
-        Ok(Self {
-            highlighter: std::sync::Mutex::new(highlighter),
-        })
+        self.highlighter
+            .add_tree_sitter_language(languages, config)?;
+        Ok(self)
     }
 
     #[cfg(feature = "lang-vixen")]
@@ -676,25 +2072,26 @@ impl CompareHandler {
 
     /// Parse the compare block content into sections.
     ///
-    /// Each section starts with `/// language` and contains the code until
-    /// the next separator or end of content.
+    /// Each section starts with `/// language` or `/// language "Label"` and
+    /// contains the code until the next separator or end of content.
     pub fn parse_sections(code: &str) -> Vec<CompareSection> {
         let mut sections = Vec::new();
-        let mut current_language: Option<String> = None;
+        let mut current: Option<(String, Option<String>)> = None;
         let mut current_code = String::new();
 
         for line in code.lines() {
-            if let Some(lang) = line.strip_prefix("/// ") {
+            if let Some(separator) = line.strip_prefix("/// ") {
                 // Start a new section - save previous if exists
-                if let Some(lang) = current_language.take() {
+                if let Some((language, label)) = current.take() {
                     sections.push(CompareSection {
-                        language: lang,
+                        language,
+                        label,
                         code: current_code.trim_end().to_string(),
                     });
                     current_code.clear();
                 }
-                current_language = Some(lang.trim().to_string());
-            } else if current_language.is_some() {
+                current = Some(Self::parse_separator(separator));
+            } else if current.is_some() {
                 // Accumulate code in current section
                 if !current_code.is_empty() {
                     current_code.push('\n');
@@ -705,9 +2102,10 @@ impl CompareHandler {
         }
 
         // Don't forget the last section
-        if let Some(lang) = current_language {
+        if let Some((language, label)) = current {
             sections.push(CompareSection {
-                language: lang,
+                language,
+                label,
                 code: current_code.trim_end().to_string(),
             });
         }
@@ -715,6 +2113,23 @@ impl CompareHandler {
         sections
     }
 
+    /// Split `rust "Before"` into a language and an optional label.
+    fn parse_separator(separator: &str) -> (String, Option<String>) {
+        let separator = separator.trim();
+        let Some((language, label)) = separator.split_once(char::is_whitespace) else {
+            return (separator.to_string(), None);
+        };
+        let label = label.trim();
+        let label = ['"', '\'']
+            .iter()
+            .find_map(|&q| label.strip_prefix(q).and_then(|l| l.strip_suffix(q)))
+            .unwrap_or(label);
+        (
+            language.to_string(),
+            (!label.is_empty()).then(|| label.to_string()),
+        )
+    }
+
     /// Highlight code using arborium, with fallback for unsupported languages.
     fn highlight_code(&self, language: &str, code: &str) -> String {
         use crate::handler::html_escape;
@@ -723,8 +2138,7 @@ impl CompareHandler {
             return html_escape(code);
         }
 
-        let mut hl = self.highlighter.lock().unwrap();
-        match hl.highlight(language, code) {
+        match self.highlighter.highlight(language, code) {
             Ok(html) => html,
             Err(_) => html_escape(code),
         }
@@ -739,49 +2153,84 @@ impl Default for CompareHandler {
 }
 
 #[cfg(feature = "highlight")]
-impl CodeBlockHandler for CompareHandler {
-    fn render<'a>(
-        &'a self,
-        _language: &'a str,
-        code: &'a str,
-    ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
-        Box::pin(async move {
-            use crate::handler::html_escape;
+impl CompareHandler {
+    fn render_block(&self, code: &str, tabbed: bool) -> CodeBlockOutput {
+        use crate::handler::html_escape;
 
-            let sections = Self::parse_sections(code);
+        let sections = Self::parse_sections(code);
 
-            if sections.is_empty() {
-                // No valid sections found - render as plain text
-                let escaped = html_escape(code);
-                return Ok(format!(
-                    "<div class=\"code-block\"><pre><code>{escaped}</code></pre></div>"
-                )
-                .into());
-            }
+        if sections.is_empty() {
+            // No valid sections found - render as plain text
+            let escaped = html_escape(code);
+            return format!("<div class=\"code-block\"><pre><code>{escaped}</code></pre></div>")
+                .into();
+        }
 
-            let mut html = String::new();
+        let keys = crate::handler::tab_keys(sections.iter().map(CompareSection::title));
+        let mut html = String::new();
+        if tabbed {
+            html.push_str("<div class=\"compare-container compare-tabs\" data-tabs=\"compare\">");
+            html.push_str(&crate::handler::tab_list(
+                "compare",
+                keys.iter()
+                    .map(String::as_str)
+                    .zip(sections.iter().map(CompareSection::title)),
+            ));
+        } else {
             html.push_str("<div class=\"compare-container\">");
+        }
 
-            for section in &sections {
-                let highlighted = self.highlight_code(&section.language, &section.code);
-                let escaped_lang = html_escape(&section.language);
+        for (section, key) in sections.iter().zip(&keys) {
+            let highlighted = self.highlight_code(&section.language, &section.code);
+            let escaped_lang = html_escape(&section.language);
+            let title = html_escape(section.title());
 
-                html.push_str("<div class=\"compare-section\">");
-                html.push_str(&format!(
-                    "<div class=\"compare-header\">{}</div>",
-                    escaped_lang
-                ));
+            if tabbed {
                 html.push_str(&format!(
-                    "<div class=\"code-block\"><pre><code class=\"language-{}\">{}</code></pre></div>",
-                    escaped_lang, highlighted
+                    "<div class=\"compare-section\" role=\"tabpanel\" data-tab=\"{}\">",
+                    html_escape(key)
                 ));
-                html.push_str("</div>");
+            } else {
+                html.push_str("<div class=\"compare-section\">");
+                html.push_str(&format!("<div class=\"compare-header\">{title}</div>"));
             }
-
+            html.push_str(&format!(
+                "<div class=\"code-block\"><pre><code class=\"language-{}\">{}</code></pre></div>",
+                escaped_lang, highlighted
+            ));
             html.push_str("</div>");
+        }
 
-            Ok(html.into())
-        })
+        html.push_str("</div>");
+
+        CodeBlockOutput {
+            html,
+            head_injections: if tabbed {
+                vec![crate::handler::tabs_injection()]
+            } else {
+                Vec::new()
+            },
+            assets: Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "highlight")]
+impl CodeBlockHandler for CompareHandler {
+    fn render<'a>(
+        &'a self,
+        _language: &'a str,
+        code: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
+        Box::pin(async move { Ok(self.render_block(code, self.tabs)) })
+    }
+
+    fn render_with_info<'a>(
+        &'a self,
+        info: &'a CodeBlockInfo,
+        code: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<CodeBlockOutput>> + Send + 'a>> {
+        Box::pin(async move { Ok(self.render_block(code, self.tabs || info.has_flag("tabs"))) })
     }
 }
 
@@ -794,10 +2243,161 @@ mod tests {
         use super::*;
 
         #[test]
-        fn test_normalize_arborium_language_aliases() {
-            assert_eq!(normalize_arborium_language("jinja"), "jinja2");
-            assert_eq!(normalize_arborium_language("vx"), "vixen");
-            assert_eq!(normalize_arborium_language("rust"), "rust");
+        fn test_arborium_language_aliases() {
+            let engine = ArboriumEngine::new();
+            assert_eq!(engine.resolve_alias("jinja"), "jinja2");
+            assert_eq!(engine.resolve_alias("vx"), "vixen");
+            assert_eq!(engine.resolve_alias("rust"), "rust");
+
+            let handler = ArboriumHandler::new().with_language_alias("rust-ish", "rust");
+            assert!(handler.supports_language("rs"));
+            assert!(handler.supports_language("rust-ish"));
+            assert!(!handler.supports_language("not-a-language"));
+
+            let languages = handler.supported_languages();
+            assert!(languages.is_sorted());
+            for language in ["rust", "rs", "rust-ish"] {
+                assert!(languages.iter().any(|l| l == language), "{language}");
+            }
+            assert!(!languages.iter().any(|l| l == "not-a-language"));
+        }
+
+        #[test]
+        fn test_split_html_lines_reopens_multiline_spans() {
+            let lines = split_html_lines("<a-s>\"one\ntwo\"</a-s> <a-k>x</a-k>\nthree");
+            assert_eq!(
+                lines,
+                ["<a-s>\"one</a-s>", "<a-s>two\"</a-s> <a-k>x</a-k>", "three"]
+            );
+        }
+
+        #[test]
+        fn test_engine_pools_parse_contexts() {
+            let engine = ArboriumEngine::new();
+            let outputs: Vec<_> = std::thread::scope(|scope| {
+                let handles: Vec<_> = (0..4)
+                    .map(|_| scope.spawn(|| engine.highlight("rust", "fn main() {}").unwrap()))
+                    .collect();
+                handles.into_iter().map(|h| h.join().unwrap()).collect()
+            });
+
+            assert!(outputs.windows(2).all(|pair| pair[0] == pair[1]));
+            let pooled = engine.contexts.lock().unwrap().len();
+            assert!((1..=4).contains(&pooled), "{pooled} pooled contexts");
+
+            engine.highlight("rust", "let x = 1;").unwrap();
+            assert_eq!(engine.contexts.lock().unwrap().len(), pooled);
+        }
+
+        #[test]
+        fn test_inline_handler_languages() {
+            use crate::handler::InlineCodeHandler;
+
+            let handler = ArboriumInlineHandler::new();
+            assert_eq!(handler.render("no language"), None);
+            // Prefixes are opt-in, so paths that look like one stay plain
+            assert_eq!(handler.render(r"c:\Windows"), None);
+            assert_eq!(handler.render("rust:let x = 1;"), None);
+
+            let handler = handler.with_language_prefix(true);
+            assert_eq!(handler.render("https://example.com"), None);
+            let prefixed = handler.render("rust:let x = 1;").unwrap();
+            assert!(
+                prefixed.starts_with("<code class=\"language-rust\">")
+                    && !prefixed.contains("rust:"),
+                "{prefixed}"
+            );
+            let attributed = handler
+                .render_with_language("let x = 1;", Some("rust"))
+                .unwrap();
+            assert_eq!(attributed, prefixed);
+
+            let handler = handler
+                .with_language_prefix(false)
+                .with_default_language("not-a-language");
+            assert_eq!(
+                handler.render("rust:a<b").unwrap(),
+                "<code class=\"language-not-a-language\">rust:a&lt;b</code>"
+            );
+        }
+
+        #[tokio::test]
+        async fn test_language_aware_diff() {
+            let handler = ArboriumHandler::new().with_language_header(false);
+            let code = "--- a/main.x\n+++ b/main.x\n@@ -1,2 +1,2 @@\n a < b\n-old\n+new\n";
+
+            let dashed = handler.render("diff-not-a-language", code).await.unwrap();
+            assert!(
+                dashed.html.contains(
+                    "<span class=\"diff-line diff-meta\">--- a/main.x</span>\n<span class=\"diff-line diff-meta\">+++ b/main.x</span>\n<span class=\"diff-line diff-meta\">@@ -1,2 +1,2 @@</span>\n<span class=\"diff-line diff-context\"><span class=\"diff-marker\"> </span>a &lt; b</span>\n<span class=\"diff-line diff-remove\"><span class=\"diff-marker\">-</span>old</span>\n<span class=\"diff-line diff-add\"><span class=\"diff-marker\">+</span>new</span>"
+                ),
+                "{}",
+                dashed.html
+            );
+            assert!(dashed.html.starts_with("<div class=\"code-block diff\""));
+
+            let info = CodeBlockInfo::parse("diff,lang=not-a-language");
+            let attr = handler.render_with_info(&info, code).await.unwrap();
+            assert!(
+                attr.html.contains("<span class=\"diff-line diff-add\">"),
+                "{}",
+                attr.html
+            );
+
+            let plain = handler.render("diff", code).await.unwrap();
+            assert!(!plain.html.contains("diff-line"), "{}", plain.html);
+        }
+
+        #[tokio::test]
+        async fn test_line_numbers_and_highlighted_lines() {
+            let handler = ArboriumHandler::new().with_language_header(false);
+            let info = CodeBlockInfo::parse("not-a-language linenostart=10 {2}");
+            let output = handler
+                .render_with_info(&info, "a < b\nc\nd")
+                .await
+                .unwrap();
+
+            assert!(
+                output
+                    .html
+                    .starts_with(r#"<div class="code-block line-numbers""#),
+                "{}",
+                output.html
+            );
+            assert!(
+                output.html.contains(
+                    "<span class=\"line\" data-line=\"10\">a &lt; b</span>\n<span class=\"line highlighted\" data-line=\"11\">c</span>\n<span class=\"line\" data-line=\"12\">d</span>"
+                ),
+                "{}",
+                output.html
+            );
+
+            let info = CodeBlockInfo::parse("not-a-language linenos linenostart=abc");
+            let output = handler.render_with_info(&info, "a\nb").await.unwrap();
+            assert!(
+                output.html.contains(
+                    "<span class=\"line\" data-line=\"1\">a</span>\n<span class=\"line\" data-line=\"2\">b</span>"
+                ),
+                "{}",
+                output.html
+            );
+        }
+
+        #[tokio::test]
+        async fn test_title_header_and_copy_button() {
+            let handler = ArboriumHandler::new().with_copy_button(true);
+            let info = CodeBlockInfo::parse(r#"not-a-language file="src/<main>.rs""#);
+            let output = handler.render_with_info(&info, "x").await.unwrap();
+
+            assert!(
+                output.html.contains(
+                    "<div class=\"code-header\"><span class=\"code-title\">src/&lt;main&gt;.rs</span><span class=\"code-lang\">not-a-language</span><button class=\"code-copy\""
+                ),
+                "{}",
+                output.html
+            );
+            assert_eq!(output.head_injections.len(), 1);
+            assert_eq!(output.head_injections[0].key, "code-copy");
         }
 
         #[cfg(feature = "lang-vixen")]
@@ -919,6 +2519,21 @@ format = "toml""#;
             assert_eq!(sections[2].language, "toml");
         }
 
+        #[test]
+        fn test_parse_sections_labels() {
+            let code =
+                "/// rust \"Before\"\nlet x = 1;\n/// rust After\nlet x = 2;\n/// yaml\nx: 3";
+
+            let sections = CompareHandler::parse_sections(code);
+            assert_eq!(sections.len(), 3);
+            assert_eq!(sections[0].language, "rust");
+            assert_eq!(sections[0].label.as_deref(), Some("Before"));
+            assert_eq!(sections[0].title(), "Before");
+            assert_eq!(sections[1].label.as_deref(), Some("After"));
+            assert_eq!(sections[2].label, None);
+            assert_eq!(sections[2].title(), "yaml");
+        }
+
         #[tokio::test]
         async fn test_render_compare_block() {
             let handler = CompareHandler::new();
@@ -954,6 +2569,268 @@ key: value"#;
         }
     }
 
+    #[cfg(any(feature = "aasvg", feature = "pikru", feature = "dot"))]
+    mod diagram_tests {
+        use super::*;
+
+        const SVG: &str = "<svg xmlns=\"http://www.w3.org/2000/svg\"><path/></svg>";
+
+        #[test]
+        fn test_diagram_figure_plain() {
+            assert_eq!(
+                diagram_figure(SVG, &CodeBlockInfo::from_language("pik"), "pikchr"),
+                format!("<figure class=\"diagram diagram-pikchr\">{SVG}</figure>")
+            );
+        }
+
+        #[test]
+        fn test_diagram_figure_attributes() {
+            let info = CodeBlockInfo::parse(
+                r#"pik alt="Boxes & arrows" caption="Figure 1" align=center max-width=400"#,
+            );
+            assert_eq!(
+                diagram_figure(SVG, &info, "pikchr"),
+                "<figure class=\"diagram diagram-pikchr diagram-align-center\" style=\"max-width: 400px\">\
+                 <svg role=\"img\" aria-label=\"Boxes &amp; arrows\" xmlns=\"http://www.w3.org/2000/svg\">\
+                 <title>Boxes &amp; arrows</title><path/></svg><figcaption>Figure 1</figcaption></figure>"
+            );
+
+            // Unknown alignments and unsafe widths are ignored
+            let info = CodeBlockInfo::parse(r#"pik align=middle max-width="1px;color:red""#);
+            assert_eq!(
+                diagram_figure(SVG, &info, "aasvg"),
+                format!("<figure class=\"diagram diagram-aasvg\">{SVG}</figure>")
+            );
+        }
+    }
+
+    #[cfg(feature = "pikru")]
+    mod pikru_handler_tests {
+        use super::*;
+
+        #[test]
+        fn test_remap_block_position() {
+            let message = "Parse error:  --> 2:2\n  |\n2 | @@\n  |  ^---\n  |\n  = expected EOI";
+            assert_eq!(
+                remap_block_position(message, 3),
+                "Parse error:  --> 5:2\n  |\n5 | @@\n  |  ^---\n  |\n  = expected EOI"
+            );
+            // The gutter widens when the line numbers gain a digit
+            assert_eq!(
+                remap_block_position(message, 98),
+                "Parse error:  --> 100:2\n    |\n100 | @@\n    |  ^---\n    |\n  = expected EOI"
+            );
+            assert_eq!(remap_block_position("no position", 4), "no position");
+        }
+
+        #[tokio::test]
+        async fn test_pikru_error_reported_at_markdown_line() {
+            use crate::{RenderOptions, render};
+
+            let md = "# Diagram\n\n```pik\nbox\n@@\n```\n";
+            let opts = RenderOptions::new().with_handler(&["pik"], PikruHandler::new());
+            let err = render(md, &opts).await.unwrap_err().to_string();
+            // The fence is on line 3, so the block's line 2 is line 5 of the file
+            assert!(err.contains("Parse error:  --> 5:2\n"), "{err}");
+            assert!(err.contains("\n5 | @@\n"), "{err}");
+            assert!(!err.contains("2 | @@"), "{err}");
+        }
+    }
+
+    #[cfg(feature = "dot")]
+    mod dot_handler_tests {
+        use super::*;
+
+        #[tokio::test]
+        async fn test_dot_handler_renders_inline_svg() {
+            let output = DotHandler::new()
+                .render("dot", "digraph { a -> b }")
+                .await
+                .unwrap();
+            assert!(
+                output
+                    .html
+                    .starts_with("<figure class=\"diagram diagram-dot\"><svg"),
+                "{}",
+                output.html
+            );
+
+            let err = DotHandler::new().render("dot", "a -> b").await.unwrap_err();
+            assert!(err.to_string().contains("parse error"), "{err}");
+        }
+
+        #[test]
+        fn test_dot_css_variables() {
+            let svg = r##"<svg><rect fill="white" stroke="#000000"/><text fill="black" style="font-size:14">a</text><path stroke="red"/></svg>"##;
+            assert_eq!(
+                dot_css_variables(svg),
+                r##"<svg><rect style="fill: var(--dot-bg, transparent); stroke: var(--dot-fg, currentColor)"/><text style="font-size:14; fill: var(--dot-fg, currentColor)">a</text><path stroke="red"/></svg>"##
+            );
+        }
+    }
+
+    mod ansi_handler_tests {
+        use super::*;
+
+        #[test]
+        fn test_ansi_basic_styles() {
+            assert_eq!(ansi_to_html("plain <text>"), "plain &lt;text&gt;");
+            assert_eq!(
+                ansi_to_html("\x1b[1;31merror\x1b[0m: \x1b[4mbad\x1b[24m ok"),
+                "<t-f n=\"1\"><t-b>error</t-b></t-f>: <t-u>bad</t-u> ok"
+            );
+            // Bright colours map to palette 8..15; 22 clears bold
+            assert_eq!(
+                ansi_to_html("\x1b[92;1mA\x1b[22mB\x1b[m"),
+                "<t-f n=\"10\"><t-b>A</t-b></t-f><t-f n=\"10\">B</t-f>"
+            );
+        }
+
+        #[test]
+        fn test_ansi_extended_colors() {
+            assert_eq!(
+                ansi_to_html("\x1b[38;5;196mred\x1b[38;5;3my\x1b[0m"),
+                "<t-f style=\"color: #ff0000\">red</t-f><t-f n=\"3\">y</t-f>"
+            );
+            assert_eq!(
+                ansi_to_html("\x1b[38;2;1;2;3;48;5;232mx"),
+                "<t-bg style=\"background-color: #080808\"><t-f style=\"color: #010203\">x</t-f></t-bg>"
+            );
+        }
+
+        #[test]
+        fn test_ansi_other_sequences_dropped() {
+            assert_eq!(
+                ansi_to_html("\x1b[2K\x1b]8;;https://x.y\x1b\\link\x1b]8;;\x07 done"),
+                "link done"
+            );
+        }
+
+        #[tokio::test]
+        async fn test_ansi_handler_output() {
+            let output = AnsiHandler::new()
+                .render("ansi", "\x1b[32mok\x1b[0m")
+                .await
+                .unwrap();
+            assert_eq!(
+                output.html,
+                "<div class=\"code-block term-output\"><pre><code><t-f n=\"2\">ok</t-f></code></pre></div>"
+            );
+        }
+    }
+
+    mod csv_handler_tests {
+        use super::*;
+
+        async fn render_csv(info: &str, code: &str) -> Result<CodeBlockOutput> {
+            CsvHandler::new()
+                .render_with_info(&CodeBlockInfo::parse(info), code)
+                .await
+        }
+
+        #[tokio::test]
+        async fn test_csv_header_and_numeric_alignment() {
+            let code = "Crate,Downloads,Notes\nserde,\"1,234\",\"fast, \"\"zero-copy\"\"\"\nregex,56,<ok>\n";
+            let output = render_csv("csv caption=\"Crates\"", code).await.unwrap();
+
+            assert!(output.html.starts_with(
+                "<div class=\"csv-table\"><table><caption>Crates</caption><thead><tr><th>Crate</th><th style=\"text-align: right\">Downloads</th><th>Notes</th></tr>"
+            ));
+            assert!(output.html.contains(
+                "<td>serde</td><td style=\"text-align: right\">1,234</td><td>fast, &quot;zero-copy&quot;</td>"
+            ));
+            assert!(output.html.contains("<td>&lt;ok&gt;</td>"));
+        }
+
+        #[tokio::test]
+        async fn test_csv_header_detection_and_hints() {
+            // A numeric first row is data
+            let output = render_csv("csv", "1,2\n3,4").await.unwrap();
+            assert!(!output.html.contains("<thead>"));
+            assert_eq!(
+                output
+                    .html
+                    .matches("<td style=\"text-align: right\">")
+                    .count(),
+                4
+            );
+
+            // Flags and hints override detection
+            let output = render_csv("csv,no-header align=c-", "a,b\nc,d")
+                .await
+                .unwrap();
+            assert!(!output.html.contains("<thead>"));
+            assert!(
+                output
+                    .html
+                    .contains("<td style=\"text-align: center\">a</td><td>b</td>")
+            );
+
+            let output = render_csv("tsv,header", "1\t2\n3\t4").await.unwrap();
+            assert!(
+                output
+                    .html
+                    .contains("<thead><tr><th style=\"text-align: right\">1</th>")
+            );
+
+            let output = render_csv("csv delimiter=;", "a;b\nc;d").await.unwrap();
+            assert!(output.html.contains("<th>a</th><th>b</th>"));
+        }
+
+        #[tokio::test]
+        async fn test_csv_errors() {
+            let err = render_csv("csv", "a,\"b\n").await.unwrap_err();
+            assert!(
+                err.to_string().contains("unterminated quoted field"),
+                "{err}"
+            );
+
+            let err = render_csv("csv src=data.csv", "").await.unwrap_err();
+            assert!(err.to_string().contains("no base directory"), "{err}");
+        }
+
+        #[tokio::test]
+        async fn test_csv_src_attribute() {
+            let dir = std::env::temp_dir().join(format!("marq-csv-test-{}", std::process::id()));
+            std::fs::create_dir_all(dir.join("data")).unwrap();
+            std::fs::write(dir.join("data/t.csv"), "Name,Age\nada,36\n").unwrap();
+            let handler = CsvHandler::new().with_base_dir(&dir);
+
+            let info = CodeBlockInfo::parse("csv src=\"data/t.csv\"");
+            let output = handler.render_with_info(&info, "").await.unwrap();
+            assert!(
+                output
+                    .html
+                    .contains("<td>ada</td><td style=\"text-align: right\">36</td>")
+            );
+
+            let info = CodeBlockInfo::parse("csv src=\"../t.csv\"");
+            let err = handler.render_with_info(&info, "").await.unwrap_err();
+            assert!(
+                err.to_string().contains("outside the base directory"),
+                "{err}"
+            );
+
+            // Relative to the document's directory, but still confined
+            let info = CodeBlockInfo::parse("csv src=\"../data/t.csv\"");
+            let mut context = CodeBlockContext::new(&info);
+            context.source_path = Some("guide/intro.md");
+            let output = handler.render_with_context(&context, "").await.unwrap();
+            assert!(output.html.contains("<td>ada</td>"), "{}", output.html);
+
+            let info = CodeBlockInfo::parse("csv src=\"../../t.csv\"");
+            let mut context = CodeBlockContext::new(&info);
+            context.source_path = Some("guide/intro.md");
+            let err = handler.render_with_context(&context, "").await.unwrap_err();
+            assert!(
+                err.to_string().contains("outside the base directory"),
+                "{err}"
+            );
+
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
     mod mermaid_handler_tests {
         use super::*;
 
@@ -985,6 +2862,29 @@ key: value"#;
             assert_eq!(output.head_injections.len(), 1);
             assert_eq!(output.head_injections[0].key, "mermaid");
             assert!(output.head_injections[0].html.contains("mermaid"));
+            assert!(output.head_injections[0].html.contains(MERMAID_CDN_URL));
+            assert!(output.assets.is_empty());
+        }
+
+        #[tokio::test]
+        async fn test_mermaid_handler_self_hosted() {
+            let handler = MermaidHandler::new()
+                .with_module_url("/assets/mermaid.mjs")
+                .with_theme("neutral", "it's dark")
+                .with_asset(StaticAsset::new(
+                    "mermaid.mjs",
+                    "text/javascript",
+                    b"export default {}".to_vec(),
+                ));
+            let output = handler.render("mermaid", "graph TD").await.unwrap();
+
+            let script = &output.head_injections[0].html;
+            assert!(script.contains("import mermaid from '/assets/mermaid.mjs';"));
+            assert!(!script.contains("cdn.jsdelivr.net"));
+            assert!(script.contains("? 'it\\'s dark' : 'neutral'"));
+            assert_eq!(output.assets.len(), 1);
+            assert_eq!(output.assets[0].name, "mermaid.mjs");
+            assert_eq!(&*output.assets[0].bytes, b"export default {}");
         }
     }
 }
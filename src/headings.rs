@@ -11,6 +11,9 @@ pub struct Heading {
     pub level: u8,
     /// Line number where this heading appears (1-indexed)
     pub line: usize,
+    /// Included file this heading came from, or `None` for the rendered
+    /// document. The line is relative to this file.
+    pub file: Option<String>,
 }
 
 /// Generate a URL-safe slug from text.
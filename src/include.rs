@@ -0,0 +1,362 @@
+//! Include directive expansion.
+//!
+//! Include directives are expanded textually before the document is parsed,
+//! so included blocks take part in heading hierarchy, requirement extraction
+//! and so on exactly as if they had been written inline. An [`IncludeMap`]
+//! remembers which file each byte of the expanded markdown came from.
+
+use std::future::Future;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+
+use crate::frontmatter::strip_frontmatter;
+use crate::handler::{BoxedIncludeResolver, IncludedFile};
+use crate::render::{SourceMap, offset_to_end_line, offset_to_line};
+use crate::{Error, Result};
+
+/// An include directive found in a markdown file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Directive {
+    /// Byte range of the whole directive block
+    range: Range<usize>,
+    path: String,
+    shift: i8,
+}
+
+/// A run of expanded markdown copied from one file.
+struct Segment {
+    expanded: Range<usize>,
+    /// Index into [`IncludeMap::files`]
+    file: usize,
+    /// Byte offset of the run in its own file
+    origin_start: usize,
+    /// Accumulated heading shift of the include chain
+    shift: i8,
+}
+
+struct SourceFile {
+    /// `None` for the document being rendered
+    path: Option<String>,
+    content: Arc<str>,
+}
+
+/// Maps positions in expanded markdown back to the files they came from.
+pub(crate) struct IncludeMap {
+    files: Vec<SourceFile>,
+    segments: Vec<Segment>,
+}
+
+impl IncludeMap {
+    /// Paths of all included files, in include order.
+    pub(crate) fn included_paths(&self) -> Vec<String> {
+        self.files
+            .iter()
+            .filter_map(|file| file.path.clone())
+            .collect()
+    }
+
+    fn segment_at(&self, offset: usize) -> Option<&Segment> {
+        let index = self
+            .segments
+            .partition_point(|segment| segment.expanded.start <= offset);
+        self.segments.get(index.checked_sub(1)?)
+    }
+
+    /// Heading level shift for a heading starting at `offset`.
+    pub(crate) fn heading_shift(&self, offset: usize) -> i8 {
+        self.segment_at(offset).map_or(0, |segment| segment.shift)
+    }
+
+    /// Translate a range of expanded markdown into its own file.
+    ///
+    /// Returns the file's path (`None` for the document being rendered), the
+    /// range within that file, and its 1-indexed start and end lines.
+    pub(crate) fn locate(&self, range: Range<usize>) -> Option<Located<'_>> {
+        let segment = self.segment_at(range.start)?;
+        let file = &self.files[segment.file];
+        let start = segment.origin_start + (range.start - segment.expanded.start);
+        let end =
+            segment.origin_start + (range.end.min(segment.expanded.end) - segment.expanded.start);
+        let end = end.max(start);
+
+        Some(Located {
+            path: file.path.as_deref(),
+            line_start: offset_to_line(&file.content, start),
+            line_end: offset_to_end_line(&file.content, end),
+            range: start..end,
+        })
+    }
+
+    /// Rewrite source-map entries to point into the file each block came from.
+    pub(crate) fn remap(&self, source_map: &mut SourceMap) {
+        for entry in &mut source_map.entries {
+            let Some(located) = self.locate(entry.byte_start..entry.byte_end) else {
+                continue;
+            };
+            entry.byte_start = located.range.start;
+            entry.byte_end = located.range.end;
+            entry.line_start = located.line_start;
+            entry.line_end = located.line_end;
+            entry.file = located.path.map(str::to_owned);
+        }
+    }
+}
+
+/// A range of expanded markdown, translated into the file it came from.
+pub(crate) struct Located<'m> {
+    pub(crate) path: Option<&'m str>,
+    pub(crate) range: Range<usize>,
+    pub(crate) line_start: usize,
+    pub(crate) line_end: usize,
+}
+
+/// Expand all include directives in `markdown`, recursively.
+pub(crate) async fn expand_includes(
+    markdown: &str,
+    resolver: &BoxedIncludeResolver,
+    source_path: Option<&str>,
+    parser_options: Options,
+) -> Result<(String, IncludeMap)> {
+    let mut map = IncludeMap {
+        files: vec![SourceFile {
+            path: None,
+            content: Arc::from(markdown),
+        }],
+        segments: Vec::new(),
+    };
+    let mut expanded = String::with_capacity(markdown.len());
+    // The root document counts as being expanded, so including itself is a cycle
+    let mut stack: Vec<String> = source_path.map(str::to_owned).into_iter().collect();
+
+    expand_file(
+        &mut Expansion {
+            out: &mut expanded,
+            map: &mut map,
+            resolver,
+            parser_options,
+            stack: &mut stack,
+        },
+        0,
+        source_path,
+        0,
+    )
+    .await?;
+
+    Ok((expanded, map))
+}
+
+struct Expansion<'e> {
+    out: &'e mut String,
+    map: &'e mut IncludeMap,
+    resolver: &'e BoxedIncludeResolver,
+    parser_options: Options,
+    /// Resolved paths of the files currently being expanded
+    stack: &'e mut Vec<String>,
+}
+
+impl Expansion<'_> {
+    fn push(&mut self, text: &str, file: usize, origin_start: usize, shift: i8) {
+        if text.is_empty() {
+            return;
+        }
+        let start = self.out.len();
+        self.out.push_str(text);
+        self.map.segments.push(Segment {
+            expanded: start..self.out.len(),
+            file,
+            origin_start,
+            shift,
+        });
+    }
+
+    /// Make sure the next block cannot be merged into the previous one.
+    fn separate(&mut self) {
+        if self.out.is_empty() || self.out.ends_with("\n\n") {
+            return;
+        }
+        if !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+        self.out.push('\n');
+    }
+}
+
+fn expand_file<'a>(
+    ex: &'a mut Expansion<'_>,
+    file: usize,
+    source_path: Option<&'a str>,
+    shift: i8,
+) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+    Box::pin(async move {
+        let content = ex.map.files[file].content.clone();
+        let body_start = if file == 0 {
+            0
+        } else {
+            content.len() - strip_frontmatter(&content).body.len()
+        };
+
+        let mut copied = body_start;
+        for directive in find_directives(&content, body_start, ex.parser_options)? {
+            ex.push(&content[copied..directive.range.start], file, copied, shift);
+            copied = directive.range.end;
+
+            let IncludedFile {
+                path,
+                content: included,
+            } = ex.resolver.resolve(&directive.path, source_path).await?;
+
+            if ex.stack.contains(&path) {
+                let chain = ex
+                    .stack
+                    .iter()
+                    .map(String::as_str)
+                    .chain([path.as_str()])
+                    .collect::<Vec<_>>()
+                    .join(" -> ");
+                return Err(Error::Include {
+                    path,
+                    message: format!("include cycle: {}", chain),
+                });
+            }
+
+            let index = ex.map.files.len();
+            ex.map.files.push(SourceFile {
+                path: Some(path.clone()),
+                content: Arc::from(included),
+            });
+
+            ex.separate();
+            ex.stack.push(path.clone());
+            expand_file(
+                ex,
+                index,
+                Some(&path),
+                shift.saturating_add(directive.shift),
+            )
+            .await?;
+            ex.stack.pop();
+            ex.separate();
+        }
+
+        ex.push(&content[copied..], file, copied, shift);
+        Ok(())
+    })
+}
+
+/// Find top-level include directives, skipping anything before `body_start`.
+fn find_directives(
+    content: &str,
+    body_start: usize,
+    parser_options: Options,
+) -> Result<Vec<Directive>> {
+    let mut directives = Vec::new();
+    let mut depth = 0usize;
+
+    for (event, range) in Parser::new_ext(&content[body_start..], parser_options).into_offset_iter()
+    {
+        match event {
+            Event::Start(tag) => {
+                if depth == 0
+                    && matches!(tag, Tag::HtmlBlock | Tag::Paragraph)
+                    && let Some((path, shift)) =
+                        parse_directive(content[body_start..][range.clone()].trim())?
+                {
+                    directives.push(Directive {
+                        range: body_start + range.start..body_start + range.end,
+                        path,
+                        shift,
+                    });
+                }
+                depth += 1;
+            }
+            Event::End(_) => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    Ok(directives)
+}
+
+/// Parse `<!-- include path [shift=N] -->` or `{{#include path [shift=N]}}`.
+///
+/// Returns `None` for blocks that are not include directives, and an error for
+/// directives with an invalid `shift`.
+fn parse_directive(block: &str) -> Result<Option<(String, i8)>> {
+    let args = block
+        .strip_prefix("<!--")
+        .and_then(|rest| rest.strip_suffix("-->"))
+        .and_then(|rest| rest.trim_start().strip_prefix("include"))
+        .or_else(|| {
+            block
+                .strip_prefix("{{#include")
+                .and_then(|rest| rest.strip_suffix("}}"))
+        });
+    let Some(args) = args.filter(|args| args.starts_with(char::is_whitespace)) else {
+        return Ok(None);
+    };
+
+    let mut parts = args.split_whitespace();
+    let Some(path) = parts.next() else {
+        return Ok(None);
+    };
+    let mut shift = 0;
+    for part in parts {
+        if let Some(value) = part.strip_prefix("shift=") {
+            shift = value.parse().map_err(|_| Error::Include {
+                path: path.to_string(),
+                message: format!("invalid shift `{value}`: expected a number from -128 to 127"),
+            })?;
+        }
+    }
+    Ok(Some((path.to_string(), shift)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_directive() {
+        assert_eq!(
+            parse_directive("<!-- include shared/intro.md -->").unwrap(),
+            Some(("shared/intro.md".to_string(), 0))
+        );
+        assert_eq!(
+            parse_directive("{{#include intro.md shift=2}}").unwrap(),
+            Some(("intro.md".to_string(), 2))
+        );
+        assert_eq!(parse_directive("<!-- includes are nice -->").unwrap(), None);
+        assert_eq!(
+            parse_directive("<!-- note: include this -->").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_directive_invalid_shift() {
+        for directive in [
+            "{{#include a.md shift=x}}",
+            "<!-- include a.md shift=99999 -->",
+        ] {
+            let err = parse_directive(directive).unwrap_err();
+            assert!(
+                matches!(&err, Error::Include { path, message } if path == "a.md" && message.starts_with("invalid shift")),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_find_directives_skips_code_and_nested_blocks() {
+        let md = "Intro\n\n<!-- include a.md -->\n\n```\n{{#include b.md}}\n```\n\n> {{#include c.md}}\n\n{{#include d.md}}\n";
+        let paths: Vec<_> = find_directives(md, 0, Options::empty())
+            .unwrap()
+            .into_iter()
+            .map(|directive| directive.path)
+            .collect();
+        assert_eq!(paths, ["a.md", "d.md"]);
+    }
+}
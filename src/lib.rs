@@ -7,7 +7,14 @@
 //! - **Headings**: Automatic extraction with slug generation for TOC
 //! - **Requirement definitions**: req annotation syntax for specification traceability
 //! - **Code blocks**: Pluggable handlers for syntax highlighting, diagrams, etc.
+//! - **Code groups**: Consecutive `group`-flagged fences rendered as tabs
+//! - **Doc-tests**: Rust examples extracted with rustdoc flags and hidden lines
 //! - **Link resolution**: `@/path` absolute links and relative link handling
+//! - **Includes**: `<!-- include path.md -->` transclusion through an [`IncludeResolver`]
+//! - **Glossary**: Definition-list terms with anchors and optional automatic term linking
+//! - **Variables**: Opt-in `{{ page.title }}` / `{{ extra.version }}` substitution
+//! - **Typography**: Locale-aware quotes, dashes and non-breaking spaces
+//! - **Emoji**: GitHub `:shortcode:` expansion (with the `emoji` feature)
 //!
 //! ## Example
 //!
@@ -23,21 +30,36 @@
 //! ```
 
 pub mod ast;
+mod cache;
+mod code_group;
 pub mod diff;
+mod doctest;
+#[cfg(feature = "emoji")]
+mod emoji;
+mod fence;
 mod frontmatter;
+mod glossary;
 mod handler;
 mod handlers;
 mod headings;
+mod include;
 mod links;
 mod note;
 mod render;
 mod reqs;
+mod typography;
+mod variables;
 
+pub use cache::CachedHandler;
+pub use doctest::{DocTest, doc_test_file};
+pub use fence::CodeBlockInfo;
 pub use frontmatter::{Frontmatter, FrontmatterFormat, parse_frontmatter, strip_frontmatter};
+pub use glossary::GlossaryEntry;
 pub use handler::{
-    BoxedHandler, BoxedInlineCodeHandler, BoxedLinkResolver, BoxedReqHandler,
-    BoxedWikiLinkResolver, CodeBlockHandler, CodeBlockOutput, DefaultReqHandler, HeadInjection,
-    InlineCodeHandler, LinkResolver, ReqHandler, WikiLink, WikiLinkOutput, WikiLinkResolver,
+    BoxedHandler, BoxedIncludeResolver, BoxedInlineCodeHandler, BoxedLinkResolver, BoxedReqHandler,
+    BoxedWikiLinkResolver, CodeBlockContext, CodeBlockHandler, CodeBlockOutput, DefaultReqHandler,
+    HeadInjection, IncludeResolver, IncludedFile, InlineCodeHandler, LinkResolver, RawCodeHandler,
+    ReqHandler, StaticAsset, WikiLink, WikiLinkOutput, WikiLinkResolver,
 };
 pub use headings::{Heading, slugify};
 pub use links::resolve_link;
@@ -45,8 +67,8 @@ pub use note::{
     MARK_TAG, Note, NoteMeta, parse_note, render_aside, strip_marks, to_comment, wrap_mark,
 };
 pub use render::{
-    DocElement, Document, Paragraph, RenderOptions, SourceId, SourceKind, SourceMap,
-    SourceMapEntry, render,
+    CodeBlockDiagnostic, CodeSample, DocElement, Document, HandlerErrorPolicy, Paragraph,
+    RenderOptions, SourceId, SourceKind, SourceMap, SourceMapEntry, render,
 };
 pub use reqs::{
     ExtractedReqs, InlineCodeSpan, ReqDefinition, ReqLevel, ReqMetadata, ReqStatus, ReqWarning,
@@ -58,7 +80,7 @@ pub use diff::{diff_markdown, diff_markdown_inline};
 
 // Feature-gated handler exports
 #[cfg(feature = "highlight")]
-pub use handlers::ArboriumHandler;
+pub use handlers::{ArboriumHandler, ArboriumInlineHandler};
 
 #[cfg(feature = "highlight")]
 pub use handlers::{CompareHandler, CompareSection};
@@ -69,9 +91,14 @@ pub use handlers::AasvgHandler;
 #[cfg(feature = "pikru")]
 pub use handlers::PikruHandler;
 
+#[cfg(feature = "dot")]
+pub use handlers::DotHandler;
+
 // Always-available handlers
-pub use handlers::MermaidHandler;
+pub use handlers::AnsiHandler;
+pub use handlers::CsvHandler;
 pub use handlers::TermHandler;
+pub use handlers::{MERMAID_CDN_URL, MermaidHandler};
 
 /// Error type for marq operations.
 #[derive(Debug, thiserror::Error)]
@@ -87,6 +114,10 @@ pub enum Error {
     /// Code block handler failed
     #[error("code block handler error for language '{language}': {message}")]
     CodeBlockHandler { language: String, message: String },
+
+    /// Include directive could not be expanded
+    #[error("include error for '{path}': {message}")]
+    Include { path: String, message: String },
 }
 
 /// Result type alias for marq operations.
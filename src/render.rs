@@ -1,25 +1,38 @@
 //! Main rendering pipeline.
 
+use std::borrow::Cow;
 use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::ops::Range;
 use std::sync::Arc;
 
 use pulldown_cmark::{
-    Alignment, BlockQuoteKind, CodeBlockKind, Event, LinkType, MetadataBlockKind, Options, Parser,
-    Tag, TagEnd,
+    Alignment, BlockQuoteKind, CodeBlockKind, CowStr, Event, LinkType, MetadataBlockKind, Options,
+    Parser, Tag, TagEnd, TextMergeWithOffset,
 };
 
 use crate::Result;
+use crate::code_group::{self, CodeGroup};
+use crate::doctest;
+use crate::fence::CodeBlockInfo;
+use crate::frontmatter::strip_frontmatter;
 use crate::frontmatter::{Frontmatter, FrontmatterFormat};
+use crate::glossary::{GlossaryCollector, GlossaryEntry, link_terms};
 use crate::handler::{
-    BoxedHandler, BoxedInlineCodeHandler, BoxedLinkResolver, BoxedReqHandler,
-    BoxedWikiLinkResolver, CodeBlockHandler, CodeBlockOutput, DefaultReqHandler, InlineCodeHandler,
-    RawCodeHandler, ReqHandler, WikiLink, WikiLinkOutput, WikiLinkResolver, html_escape,
+    BoxedHandler, BoxedIncludeResolver, BoxedInlineCodeHandler, BoxedLinkResolver, BoxedReqHandler,
+    BoxedWikiLinkResolver, CodeBlockContext, CodeBlockHandler, CodeBlockOutput, DefaultReqHandler,
+    HeadInjection, IncludeResolver, InlineCodeHandler, RawCodeHandler, ReqHandler, StaticAsset,
+    WikiLink, WikiLinkOutput, WikiLinkResolver, html_escape,
 };
 use crate::headings::{Heading, slugify};
+use crate::include::{IncludeMap, expand_includes};
 use crate::links::resolve_link;
-use crate::reqs::{InlineCodeSpan, ReqDefinition, RuleId, SourceSpan, parse_req_marker};
+use crate::reqs::{
+    InlineCodeSpan, ReqDefinition, ReqWarning, RuleId, SourceSpan, analyze_req_content,
+    parse_req_marker,
+};
+use crate::typography::{self, Locale};
+use crate::variables::VariableExpander;
 
 /// Parse context representing the current nested structure we're inside.
 /// This replaces the ad-hoc state variables with a proper stack.
@@ -55,10 +68,9 @@ enum ParseContext<'a> {
 
     /// Inside a code block
     CodeBlock {
-        full_language: String,
-        base_language: String,
+        info: CodeBlockInfo,
         code: String,
-        line: usize,
+        range: Range<usize>,
     },
 }
 
@@ -90,6 +102,9 @@ pub struct Paragraph {
     pub line: usize,
     /// Byte offset where this paragraph starts
     pub offset: usize,
+    /// Included file this paragraph came from, or `None` for the rendered
+    /// document. The line and offset are relative to this file.
+    pub file: Option<String>,
 }
 
 /// An element in the document, in document order.
@@ -152,6 +167,14 @@ pub struct RenderOptions {
     /// Custom handler for rendering requirement definitions
     pub req_handler: Option<BoxedReqHandler>,
 
+    /// Requirement marker prefixes to recognize (the `r` in `r[…]`), each
+    /// with an optional handler used instead of [`req_handler`](Self::req_handler).
+    ///
+    /// When empty, any lowercase alphanumeric prefix starts a requirement.
+    /// Otherwise paragraphs starting with other prefixes (`note[1]`, `a[i]`)
+    /// are left as ordinary text.
+    pub req_prefixes: BTreeMap<String, Option<BoxedReqHandler>>,
+
     /// Custom handler for rendering inline code spans
     pub inline_code_handler: Option<BoxedInlineCodeHandler>,
 
@@ -160,6 +183,80 @@ pub struct RenderOptions {
 
     /// Custom handler for resolving wiki-style links.
     pub wiki_link_resolver: Option<BoxedWikiLinkResolver>,
+
+    /// Loader for include directives. Directives are left untouched when unset.
+    pub include_resolver: Option<BoxedIncludeResolver>,
+
+    /// Whether to link the first occurrence of each glossary term to its definition.
+    ///
+    /// Terms come from the document's own definition lists and from [`glossary`](Self::glossary).
+    pub link_glossary_terms: bool,
+
+    /// Glossary entries defined outside this document (e.g., a site-wide glossary page).
+    pub glossary: Vec<GlossaryEntry>,
+
+    /// Whether to apply typography rules to prose text.
+    ///
+    /// Enables smart punctuation and the locale-specific rules for [`lang`](Self::lang).
+    pub typography: bool,
+
+    /// Document language used for typography (e.g., "fr", "de-CH").
+    ///
+    /// A `lang` key in the frontmatter `extra` table takes precedence. Defaults to English.
+    pub lang: Option<String>,
+
+    /// Whether to expand `{{ page.title }}`, `{{ extra.version }}` and
+    /// [`variables`](Self::variables) references in text and link targets.
+    ///
+    /// Link destinations cannot contain spaces, so write `{{version}}` there.
+    pub substitute_variables: bool,
+
+    /// Caller-provided variables for substitution, looked up by their full name.
+    pub variables: BTreeMap<String, String>,
+
+    /// Whether variable substitution also applies to code spans and code blocks.
+    pub substitute_variables_in_code: bool,
+
+    /// Whether to expand GitHub emoji shortcodes such as `:rocket:` in text.
+    ///
+    /// Code spans, code blocks, link text and URLs, and requirement markers are left alone.
+    #[cfg(feature = "emoji")]
+    pub emoji_shortcodes: bool,
+
+    /// What to do when a code block handler fails. Defaults to aborting the render.
+    pub handler_errors: HandlerErrorPolicy,
+
+    /// Whether to drop rustdoc-style hidden lines (`# `) from Rust code blocks
+    /// in the HTML. Code samples and [`Document::doc_tests`] keep them.
+    pub hide_rust_lines: bool,
+}
+
+/// What [`render`] does when a code block handler returns an error.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HandlerErrorPolicy {
+    /// Stop rendering and return the handler's error.
+    #[default]
+    Abort,
+    /// Render an error box with the message and the escaped source in place of
+    /// the block, record a [`CodeBlockDiagnostic`], and keep rendering.
+    ///
+    /// Useful for live previews, where a diagram is often mid-edit.
+    Inline,
+}
+
+/// A code block whose handler failed under [`HandlerErrorPolicy::Inline`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlockDiagnostic {
+    /// File containing the block, if known (included files report their own path)
+    pub source_path: Option<String>,
+    /// Line of the opening fence (1-indexed)
+    pub line: usize,
+    /// Byte span of the whole block in its file
+    pub span: SourceSpan,
+    /// Language of the block
+    pub language: String,
+    /// The handler's error message
+    pub message: String,
 }
 
 impl RenderOptions {
@@ -194,6 +291,39 @@ impl RenderOptions {
         self
     }
 
+    /// Recognize requirement markers with this prefix (e.g., `"r"`, `"sec"`).
+    ///
+    /// Once any prefix is declared, markers with undeclared prefixes are not requirements.
+    pub fn with_req_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.req_prefixes.entry(prefix.into()).or_insert(None);
+        self
+    }
+
+    /// Recognize requirement markers with this prefix, rendered by `handler`.
+    pub fn with_req_prefix_handler<H: ReqHandler + 'static>(
+        mut self,
+        prefix: impl Into<String>,
+        handler: H,
+    ) -> Self {
+        self.req_prefixes
+            .insert(prefix.into(), Some(Arc::new(handler)));
+        self
+    }
+
+    /// The handler for requirements with `prefix`, or `None` if the prefix isn't allowed.
+    fn req_handler_for<'a>(
+        &'a self,
+        prefix: &str,
+        default: &'a BoxedReqHandler,
+    ) -> Option<&'a BoxedReqHandler> {
+        if self.req_prefixes.is_empty() {
+            return Some(default);
+        }
+        self.req_prefixes
+            .get(prefix)
+            .map(|handler| handler.as_ref().unwrap_or(default))
+    }
+
     /// Set the source file path for link resolution.
     pub fn with_source_path(mut self, path: &str) -> Self {
         self.source_path = Some(path.to_string());
@@ -233,6 +363,73 @@ impl RenderOptions {
         self.wiki_link_resolver = Some(Arc::new(resolver));
         self
     }
+
+    /// Set an include resolver for `<!-- include path.md -->` directives.
+    pub fn with_include_resolver<R: IncludeResolver + 'static>(mut self, resolver: R) -> Self {
+        self.include_resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Configure whether glossary terms are linked to their definitions.
+    pub fn with_glossary_links(mut self, enabled: bool) -> Self {
+        self.link_glossary_terms = enabled;
+        self
+    }
+
+    /// Add glossary entries defined outside this document.
+    pub fn with_glossary(mut self, entries: impl IntoIterator<Item = GlossaryEntry>) -> Self {
+        self.glossary.extend(entries);
+        self
+    }
+
+    /// Configure whether typography rules are applied.
+    pub fn with_typography(mut self, enabled: bool) -> Self {
+        self.typography = enabled;
+        self
+    }
+
+    /// Set the document language used for typography.
+    pub fn with_lang(mut self, lang: impl Into<String>) -> Self {
+        self.lang = Some(lang.into());
+        self
+    }
+
+    /// Configure whether variable references are substituted.
+    pub fn with_variable_substitution(mut self, enabled: bool) -> Self {
+        self.substitute_variables = enabled;
+        self
+    }
+
+    /// Add a variable for substitution (e.g., `version` for `{{ version }}`).
+    pub fn with_variable(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.variables.insert(name.into(), value.into());
+        self
+    }
+
+    /// Configure whether variables are also substituted inside code.
+    pub fn with_variables_in_code(mut self, enabled: bool) -> Self {
+        self.substitute_variables_in_code = enabled;
+        self
+    }
+
+    /// Configure whether emoji shortcodes are expanded.
+    #[cfg(feature = "emoji")]
+    pub fn with_emoji_shortcodes(mut self, enabled: bool) -> Self {
+        self.emoji_shortcodes = enabled;
+        self
+    }
+
+    /// Set what happens when a code block handler fails.
+    pub fn with_handler_error_policy(mut self, policy: HandlerErrorPolicy) -> Self {
+        self.handler_errors = policy;
+        self
+    }
+
+    /// Enable or disable hiding `# ` lines in Rust code blocks.
+    pub fn with_hidden_rust_lines(mut self, enabled: bool) -> Self {
+        self.hide_rust_lines = enabled;
+        self
+    }
 }
 
 /// Opaque ID for a rendered HTML element that has a source-map entry.
@@ -316,6 +513,10 @@ pub struct SourceMapEntry {
     pub byte_start: usize,
     /// Exclusive ending byte offset in the source markdown.
     pub byte_end: usize,
+    /// Included file this element came from, or `None` for the rendered document.
+    ///
+    /// Lines and byte offsets are relative to this file.
+    pub file: Option<String>,
 }
 
 /// Sidecar map from rendered `data-sid` attributes back to markdown spans.
@@ -340,16 +541,36 @@ impl SourceMap {
 }
 
 /// Render inline code, using the handler if available.
-fn render_inline_code(code: &str, handler: Option<&BoxedInlineCodeHandler>) -> String {
-    if let Some(h) = handler
-        && let Some(rendered) = h.render(code)
-    {
-        return rendered;
+fn render_inline_code(
+    code: &str,
+    range: &Range<usize>,
+    markdown: &str,
+    handler: Option<&BoxedInlineCodeHandler>,
+) -> String {
+    if let Some(h) = handler {
+        let language = inline_code_attr(markdown, range.end).map(|(language, _)| language);
+        if let Some(rendered) = h.render_with_language(code, language) {
+            return rendered;
+        }
     }
     // Default rendering
     format!("<code>{}</code>", html_escape(code))
 }
 
+/// Parse a `{.lang}` attribute directly after an inline code span ending at `offset`.
+///
+/// Returns the language and the attribute's length in bytes.
+fn inline_code_attr(markdown: &str, offset: usize) -> Option<(&str, usize)> {
+    let rest = markdown.get(offset..)?.strip_prefix("{.")?;
+    let end = rest.find('}')?;
+    let language = &rest[..end];
+    let valid = !language.is_empty()
+        && language
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '+' | '#'));
+    valid.then_some((language, end + 3))
+}
+
 /// Resolve a link using the custom resolver if available, otherwise use default resolution.
 async fn resolve_link_with_resolver(
     link: &str,
@@ -366,8 +587,22 @@ async fn resolve_link_with_resolver(
     resolve_link(link, source_path)
 }
 
-fn render_text(html: &mut String, text: &str) {
-    html.push_str(&html_escape(text));
+fn render_text(html: &mut String, text: &str, options: &RenderOptions) {
+    #[cfg(feature = "emoji")]
+    let text = if options.emoji_shortcodes {
+        crate::emoji::expand_shortcodes(text)
+    } else {
+        Cow::Borrowed(text)
+    };
+    #[cfg(not(feature = "emoji"))]
+    let text = Cow::Borrowed(text);
+
+    if options.typography {
+        let locale = Locale::from_lang(options.lang.as_deref());
+        html.push_str(&html_escape(&typography::apply(&text, locale)));
+    } else {
+        html.push_str(&html_escape(&text));
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -484,8 +719,13 @@ pub struct CodeSample {
     pub line: usize,
     /// Full language string (e.g., "rust,test", "python,ignore")
     pub language: String,
+    /// Parsed info string, including flags, attributes and highlighted lines
+    pub info: CodeBlockInfo,
     /// The raw code content
     pub code: String,
+    /// Included file this code block came from, or `None` for the rendered
+    /// document. The line is relative to this file.
+    pub file: Option<String>,
 }
 
 /// A rendered markdown document.
@@ -520,20 +760,40 @@ pub struct Document {
     /// Already deduplicated by key during rendering.
     pub head_injections: Vec<String>,
 
+    /// Files requested by code block handlers, deduplicated by name and
+    /// sorted by it. See [`StaticAsset`].
+    pub assets: Vec<StaticAsset>,
+
     /// All inline code spans (backtick-delimited) found in the document.
     /// Spans include byte offsets covering the backtick delimiters.
     pub inline_code_spans: Vec<InlineCodeSpan>,
 
     /// Source map for rendered elements with `data-sid` attributes.
     pub source_map: SourceMap,
+
+    /// Glossary terms collected from the document's definition lists.
+    pub glossary: Vec<GlossaryEntry>,
+
+    /// Resolved paths of all files pulled in by include directives.
+    /// Useful for dependency tracking in incremental rebuilds.
+    pub includes: Vec<String>,
+
+    /// Code blocks whose handler failed, when rendering with
+    /// [`HandlerErrorPolicy::Inline`].
+    pub diagnostics: Vec<CodeBlockDiagnostic>,
+
+    /// RFC 2119 quality warnings for the requirements in [`reqs`](Self::reqs):
+    /// requirements with no keyword, and negative requirements (`MUST NOT`),
+    /// which are hard to test. Negative warnings point at the keyword.
+    pub req_warnings: Vec<ReqWarning>,
 }
 
 /// Convert a byte offset to a 1-indexed line number.
-fn offset_to_line(content: &str, offset: usize) -> usize {
+pub(crate) fn offset_to_line(content: &str, offset: usize) -> usize {
     content[..offset.min(content.len())].matches('\n').count() + 1
 }
 
-fn offset_to_end_line(content: &str, end_offset: usize) -> usize {
+pub(crate) fn offset_to_end_line(content: &str, end_offset: usize) -> usize {
     if end_offset == 0 {
         1
     } else {
@@ -631,6 +891,7 @@ impl SourceMapBuilder {
             line_end: offset_to_end_line(markdown, range.end),
             byte_start: range.start,
             byte_end: range.end,
+            file: None,
         });
         Some(id)
     }
@@ -653,6 +914,7 @@ impl SourceMapBuilder {
             line_end: offset_to_end_line(markdown, range.end),
             byte_start: range.start,
             byte_end: range.end,
+            file: None,
         });
         Some(id)
     }
@@ -724,15 +986,73 @@ fn stable_source_id(kind: SourceKind, source: &str) -> String {
 /// ```
 pub async fn render(markdown: &str, options: &RenderOptions) -> Result<Document> {
     // Parse markdown with metadata block support, using offset iterator for line tracking
-    let parser_options = Options::ENABLE_TABLES
+    let mut parser_options = Options::ENABLE_TABLES
         | Options::ENABLE_FOOTNOTES
         | Options::ENABLE_STRIKETHROUGH
         | Options::ENABLE_HEADING_ATTRIBUTES
+        | Options::ENABLE_DEFINITION_LIST
         | Options::ENABLE_YAML_STYLE_METADATA_BLOCKS
         | Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS
         | Options::ENABLE_WIKILINKS;
+    if options.typography {
+        parser_options |= Options::ENABLE_SMART_PUNCTUATION;
+    }
 
-    let parser = Parser::new_ext(markdown, parser_options).into_offset_iter();
+    // Text is rendered as it streams past, so frontmatter values that affect
+    // text rendering or code block handlers must be known before the main loop.
+    let has_frontmatter = strip_frontmatter(markdown).raw.is_some();
+    let page_frontmatter = crate::frontmatter::parse_frontmatter(markdown)
+        .ok()
+        .map(|(frontmatter, _)| frontmatter);
+
+    // Frontmatter `extra.lang` overrides the configured typography locale
+    let page_options;
+    let options = match page_frontmatter
+        .as_ref()
+        .filter(|_| options.typography)
+        .and_then(|frontmatter| frontmatter.extra_str("lang"))
+    {
+        Some(lang) => {
+            page_options = RenderOptions {
+                lang: Some(lang.to_owned()),
+                ..options.clone()
+            };
+            &page_options
+        }
+        None => options,
+    };
+
+    let mut variables = options.substitute_variables.then(|| {
+        VariableExpander::new(
+            page_frontmatter.as_ref(),
+            &options.variables,
+            options.substitute_variables_in_code,
+        )
+    });
+
+    // Expand include directives before parsing. Everything below works on the
+    // expanded markdown; the include map translates positions back afterwards.
+    let expanded;
+    let mut include_map = None;
+    let markdown = match &options.include_resolver {
+        Some(resolver) => {
+            let (text, map) = expand_includes(
+                markdown,
+                resolver,
+                options.source_path.as_deref(),
+                parser_options,
+            )
+            .await?;
+            expanded = text;
+            include_map = Some(map);
+            expanded.as_str()
+        }
+        None => markdown,
+    };
+
+    let mut parser =
+        TextMergeWithOffset::new(Parser::new_ext(markdown, parser_options).into_offset_iter())
+            .peekable();
 
     // Collected data
     let mut headings: Vec<Heading> = Vec::new();
@@ -740,7 +1060,11 @@ pub async fn render(markdown: &str, options: &RenderOptions) -> Result<Document>
     let mut elements: Vec<DocElement> = Vec::new();
     let mut code_samples: Vec<CodeSample> = Vec::new();
     let mut inline_code_spans: Vec<InlineCodeSpan> = Vec::new();
-    let mut head_injection_map: BTreeMap<String, String> = BTreeMap::new();
+    let mut resources = HandlerResources::default();
+    let mut diagnostics: Vec<CodeBlockDiagnostic> = Vec::new();
+    let mut req_warnings: Vec<ReqWarning> = Vec::new();
+    let mut code_group = CodeGroup::default();
+    let mut glossary = GlossaryCollector::default();
     let mut html_state = HtmlRenderState::default();
     let mut source_map = SourceMapBuilder::new(options);
 
@@ -771,25 +1095,55 @@ pub async fn render(markdown: &str, options: &RenderOptions) -> Result<Document>
     let req_handler = options.req_handler.as_ref().unwrap_or(&default_req_handler);
 
     // Default code handler
-    let default_code_handler: BoxedHandler = Arc::new(RawCodeHandler);
+    let default_code_handler: BoxedHandler = Arc::new(RawCodeHandler::new());
+    let code_contexts = CodeContexts {
+        markdown,
+        source_path: options.source_path.as_deref(),
+        frontmatter: page_frontmatter.as_ref().filter(|_| has_frontmatter),
+        include_map: include_map.as_ref(),
+    };
 
     // Helper to check if inside blockquote
     let is_inside_blockquote =
         |stack: &[ParseContext<'_>]| stack_contains(stack, |c| c.is_blockquote());
 
-    for (event, range) in parser {
+    while let Some((event, range)) = parser.next() {
+        let event = match &mut variables {
+            Some(variables) => variables.expand_event(event),
+            None => event,
+        };
+
+        // A `{.lang}` attribute after an inline code span is for the inline
+        // code handler; drop it from the text that follows the span.
+        if matches!(event, Event::Code(_))
+            && options.inline_code_handler.is_some()
+            && let Some((_, attr_len)) = inline_code_attr(markdown, range.end)
+            && let Some((Event::Text(text), text_range)) = parser.peek_mut()
+            && text_range.start == range.end
+        {
+            if text.len() == attr_len {
+                parser.next();
+            } else {
+                *text = CowStr::from(text[attr_len..].to_string());
+                text_range.start += attr_len;
+            }
+        }
+
         // Collect all inline code spans centrally. pulldown_cmark only emits
         // Event::Code for genuine backtick spans, never for fenced code block
         // content, so this naturally excludes code blocks (even blockquoted ones).
         if let Event::Code(code) = &event {
+            let origin = code_contexts.origin(range.clone());
             inline_code_spans.push(InlineCodeSpan {
                 content: code.to_string(),
                 span: SourceSpan {
-                    offset: range.start,
-                    length: range.len(),
+                    offset: origin.range.start,
+                    length: origin.range.len(),
                 },
+                file: origin.file.map(str::to_owned),
             });
         }
+        glossary.observe(&event, &range, markdown);
 
         // While inside an inline note comment, swallow its inner `Html` line
         // events; the whole block was already handled at its `Start(HtmlBlock)`.
@@ -811,6 +1165,7 @@ pub async fn render(markdown: &str, options: &RenderOptions) -> Result<Document>
                 let mut body_opts = options.clone();
                 body_opts.source_map = false; // note bodies don't pollute the page map
                 body_opts.render_notes = false; // notes don't nest
+                body_opts.link_glossary_terms = false; // terms are linked once per page
                 let rendered = Box::pin(render(&note.body, &body_opts)).await?;
                 html.push_str(&crate::note::render_aside(&note.meta, &rendered.html));
             }
@@ -844,7 +1199,9 @@ pub async fn render(markdown: &str, options: &RenderOptions) -> Result<Document>
 
                         // Check if this is a req
                         let trimmed = first_para_text.trim();
-                        if let Some((prefix, _, _)) = parse_req_leading_marker(trimmed) {
+                        if let Some((prefix, _, _)) = parse_req_leading_marker(trimmed)
+                            && let Some(req_handler) = options.req_handler_for(prefix, req_handler)
+                        {
                             // Find the actual marker position in the source (after the > prefix)
                             let marker = format!("{}[", prefix);
                             let marker_offset = markdown[start_offset..]
@@ -861,11 +1218,19 @@ pub async fn render(markdown: &str, options: &RenderOptions) -> Result<Document>
                             ) {
                                 match req_result {
                                     Ok(mut req) => {
+                                        req_warnings.extend(req_warnings_for(&req, &code_contexts));
+                                        code_contexts.relocate_req(&mut req);
+
                                         // Render req content HTML
                                         let content_html = render_blockquote_req_content(
                                             &events,
                                             options,
-                                            &default_code_handler,
+                                            &mut NestedCode {
+                                                contexts: &code_contexts,
+                                                default_handler: &default_code_handler,
+                                                diagnostics: &mut diagnostics,
+                                                resources: &mut resources,
+                                            },
                                         )
                                         .await?;
 
@@ -920,8 +1285,15 @@ pub async fn render(markdown: &str, options: &RenderOptions) -> Result<Document>
                                 options,
                                 markdown,
                                 &mut source_map,
+                                &glossary,
+                                Some(&mut NestedCode {
+                                    contexts: &code_contexts,
+                                    default_handler: &default_code_handler,
+                                    diagnostics: &mut diagnostics,
+                                    resources: &mut resources,
+                                }),
                             )
-                            .await;
+                            .await?;
                         }
                     }
                     continue;
@@ -984,20 +1356,21 @@ pub async fn render(markdown: &str, options: &RenderOptions) -> Result<Document>
 
             // ===== Headings =====
             Event::Start(Tag::Heading { level, .. }) => {
+                let shift = include_map
+                    .as_ref()
+                    .map_or(0, |map| map.heading_shift(range.start));
                 context_stack.push(ParseContext::Heading {
-                    level: *level as u8,
+                    level: (*level as i8).saturating_add(shift).clamp(1, 6) as u8,
                     text: String::new(),
                     start_offset: range.start,
                 });
                 // We'll emit the <h*> tag when we have the full heading text
             }
-            Event::End(TagEnd::Heading(level)) => {
-                let current_level = *level as u8;
-
+            Event::End(TagEnd::Heading(_)) => {
                 if let Some(ParseContext::Heading {
+                    level: current_level,
                     text: heading_text,
                     start_offset,
-                    ..
                 }) = context_stack.pop()
                 {
                     let slug = slugify(&heading_text);
@@ -1024,12 +1397,13 @@ pub async fn render(markdown: &str, options: &RenderOptions) -> Result<Document>
 
                     heading_stack.push((current_level, slug));
 
-                    let line = offset_to_line(markdown, start_offset);
+                    let origin = code_contexts.origin(start_offset..range.end);
                     let heading = Heading {
                         title: heading_text.clone(),
                         id: id.clone(),
                         level: current_level,
-                        line,
+                        line: origin.line,
+                        file: origin.file.map(str::to_owned),
                     };
                     headings.push(heading.clone());
                     elements.push(DocElement::Heading(heading));
@@ -1065,7 +1439,8 @@ pub async fn render(markdown: &str, options: &RenderOptions) -> Result<Document>
                     events.push((event, range));
 
                     let trimmed = paragraph_text.trim();
-                    if parse_req_leading_marker(trimmed).is_some()
+                    if let Some((prefix, _, _)) = parse_req_leading_marker(trimmed)
+                        && let Some(req_handler) = options.req_handler_for(prefix, req_handler)
                         && let Some(req_result) = try_parse_paragraph_req(
                             trimmed,
                             markdown,
@@ -1077,9 +1452,12 @@ pub async fn render(markdown: &str, options: &RenderOptions) -> Result<Document>
                     {
                         match req_result {
                             Ok(mut req) => {
+                                req_warnings.extend(req_warnings_for(&req, &code_contexts));
+                                code_contexts.relocate_req(&mut req);
+
                                 // Render req content HTML
                                 let content_html =
-                                    render_paragraph_req_content(&events, options).await;
+                                    render_paragraph_req_content(&events, options, markdown).await;
 
                                 // Store content in req.html for API access
                                 req.html = content_html.clone();
@@ -1103,43 +1481,45 @@ pub async fn render(markdown: &str, options: &RenderOptions) -> Result<Document>
                     }
 
                     // Normal paragraph
-                    let line = offset_to_line(markdown, start_offset);
+                    let origin = code_contexts.origin(start_offset..start_offset);
                     elements.push(DocElement::Paragraph(Paragraph {
-                        line,
-                        offset: start_offset,
+                        line: origin.line,
+                        offset: origin.range.start,
+                        file: origin.file.map(str::to_owned),
                     }));
-                    render_events_to_html(&mut html, &events, options, markdown, &mut source_map)
-                        .await;
+                    render_events_to_html(
+                        &mut html,
+                        &events,
+                        options,
+                        markdown,
+                        &mut source_map,
+                        &glossary,
+                        None,
+                    )
+                    .await?;
                 }
             }
 
             // ===== Code blocks =====
             Event::Start(Tag::CodeBlock(kind)) => {
-                let full_language = match kind {
-                    CodeBlockKind::Fenced(lang) => lang.split_whitespace().next().unwrap_or(""),
-                    CodeBlockKind::Indented => "",
+                let info = match kind {
+                    CodeBlockKind::Fenced(info) => CodeBlockInfo::parse(info),
+                    CodeBlockKind::Indented => CodeBlockInfo::default(),
                 };
-                let base_language = full_language.split(',').next().unwrap_or(full_language);
-                let line = offset_to_line(markdown, range.start);
                 context_stack.push(ParseContext::CodeBlock {
-                    full_language: full_language.to_string(),
-                    base_language: base_language.to_string(),
+                    info,
                     code: String::new(),
-                    line,
+                    range: range.clone(),
                 });
             }
             Event::End(TagEnd::CodeBlock) => {
-                if let Some(ParseContext::CodeBlock {
-                    full_language,
-                    base_language,
-                    code,
-                    line,
-                }) = context_stack.pop()
-                {
+                if let Some(ParseContext::CodeBlock { info, code, range }) = context_stack.pop() {
+                    let origin = code_contexts.origin(range.clone());
+
                     // Render code block
                     let handler = options
                         .code_handlers
-                        .get(&base_language)
+                        .get(&info.language)
                         .or(options.default_handler.as_ref())
                         .unwrap_or(&default_code_handler);
 
@@ -1147,19 +1527,46 @@ pub async fn render(markdown: &str, options: &RenderOptions) -> Result<Document>
                     // a newline before the closing ``` fence, which would otherwise
                     // render as extra whitespace inside the <code> element.
                     let code_trimmed = code.trim_end_matches('\n');
-                    let CodeBlockOutput {
-                        html: rendered,
-                        head_injections,
-                    } = handler.render(&base_language, code_trimmed).await?;
-                    html.push_str(&rendered);
-                    for inj in head_injections {
-                        head_injection_map.entry(inj.key).or_insert(inj.html);
+                    let grouped = code_group::group_name(&info).is_some();
+                    let handler_info = if grouped {
+                        code_group::handler_info(&info)
+                    } else {
+                        info.clone()
+                    };
+                    let context = code_contexts.context(&handler_info, range, false, false);
+                    let output = render_code_block(
+                        handler,
+                        &context,
+                        code_trimmed,
+                        options,
+                        &mut diagnostics,
+                    )
+                    .await?;
+                    let rendered = resources.keep(output);
+
+                    if grouped {
+                        // Hold the block until the next event shows whether
+                        // the group continues
+                        code_group.push(&info, rendered);
+                        let continues = matches!(
+                            parser.peek(),
+                            Some((Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(next))), _))
+                                if code_group.continues_with(&CodeBlockInfo::parse(next))
+                        );
+                        if !continues && let Some((group_html, inj)) = code_group.finish() {
+                            html.push_str(&group_html);
+                            resources.inject(inj);
+                        }
+                    } else {
+                        html.push_str(&rendered);
                     }
 
                     code_samples.push(CodeSample {
-                        line,
-                        language: full_language,
+                        line: origin.line,
+                        language: info.raw.split_whitespace().next().unwrap_or("").to_string(),
+                        info,
                         code,
+                        file: origin.file.map(str::to_owned),
                     });
                 }
             }
@@ -1198,7 +1605,7 @@ pub async fn render(markdown: &str, options: &RenderOptions) -> Result<Document>
                 }
                 None => {
                     if inline_link_stack.is_empty() {
-                        render_text(&mut html, text);
+                        render_text(&mut html, text, options);
                     } else {
                         html.push_str(&html_escape(text));
                     }
@@ -1217,6 +1624,8 @@ pub async fn render(markdown: &str, options: &RenderOptions) -> Result<Document>
                 _ => {
                     html.push_str(&render_inline_code(
                         code,
+                        &range,
+                        markdown,
                         options.inline_code_handler.as_ref(),
                     ));
                 }
@@ -1277,6 +1686,7 @@ pub async fn render(markdown: &str, options: &RenderOptions) -> Result<Document>
                         markdown,
                         &mut html_state,
                         &mut source_map,
+                        &glossary,
                     ) {
                         pulldown_cmark::html::push_html(&mut html, std::iter::once(event.clone()));
                     }
@@ -1292,7 +1702,10 @@ pub async fn render(markdown: &str, options: &RenderOptions) -> Result<Document>
         _ => None,
     };
 
-    let source_map = source_map.finish(&mut html);
+    let mut source_map = source_map.finish(&mut html);
+    if let Some(map) = &include_map {
+        map.remap(&mut source_map);
+    }
 
     // In production (notes off), strip note highlight wrappers so they leave no
     // trace in the served HTML. Note comments are already stripped inline above.
@@ -1300,6 +1713,17 @@ pub async fn render(markdown: &str, options: &RenderOptions) -> Result<Document>
         html = crate::note::strip_marks(&html);
     }
 
+    let glossary = glossary.finish();
+    if options.link_glossary_terms {
+        let mut terms: Vec<&GlossaryEntry> = glossary.iter().collect();
+        terms.extend(options.glossary.iter().filter(|external| {
+            !glossary
+                .iter()
+                .any(|entry| entry.term.eq_ignore_ascii_case(&external.term))
+        }));
+        html = link_terms(&html, &terms);
+    }
+
     Ok(Document {
         raw_metadata,
         metadata_format,
@@ -1309,25 +1733,93 @@ pub async fn render(markdown: &str, options: &RenderOptions) -> Result<Document>
         reqs,
         code_samples,
         elements,
-        head_injections: head_injection_map.into_values().collect(),
+        head_injections: resources.head_injections.into_values().collect(),
+        assets: resources.assets.into_values().collect(),
         inline_code_spans,
         source_map,
+        glossary,
+        includes: include_map
+            .map(|map| map.included_paths())
+            .unwrap_or_default(),
+        diagnostics,
+        req_warnings,
     })
 }
 
+/// Run RFC 2119 analysis over a requirement's source, reporting positions in
+/// the file the requirement came from. `req` must still have its spans in the
+/// expanded markdown, i.e. not yet be relocated.
+fn req_warnings_for(req: &ReqDefinition, contexts: &CodeContexts<'_>) -> Vec<ReqWarning> {
+    let req_range = req.span.offset..req.span.offset + req.span.length;
+    let content_start = req.marker_span.offset + req.marker_span.length;
+    let content = contexts
+        .markdown
+        .get(content_start..req_range.end)
+        .unwrap_or_default();
+
+    analyze_req_content(content)
+        .into_iter()
+        .map(|(kind, range)| {
+            let range = range.map_or(req_range.clone(), |range| {
+                content_start + range.start..content_start + range.end
+            });
+            let origin = contexts.origin(range);
+            ReqWarning {
+                file: origin
+                    .file
+                    .or(contexts.source_path)
+                    .unwrap_or_default()
+                    .into(),
+                req_id: req.id.clone(),
+                line: origin.line,
+                span: SourceSpan {
+                    offset: origin.range.start,
+                    length: origin.range.len(),
+                },
+                kind,
+            }
+        })
+        .collect()
+}
+
 /// Render a list of events to HTML string
+///
+/// Code blocks are rendered by their handlers when `nested` is given (for
+/// blockquotes), and as plain `<pre>` blocks otherwise.
 async fn render_events_to_html(
     html: &mut String,
     events: &[(Event<'_>, Range<usize>)],
     options: &RenderOptions,
     markdown: &str,
     source_map: &mut SourceMapBuilder,
-) {
+    glossary: &GlossaryCollector,
+    mut nested: Option<&mut NestedCode<'_, '_>>,
+) -> Result<()> {
     let mut html_state = HtmlRenderState::default();
     let mut link_stack: Vec<ActiveLink> = Vec::new();
     let mut i = 0;
     while i < events.len() {
         let (event, range) = &events[i];
+        if let (Event::Start(Tag::CodeBlock(kind)), Some(nested)) = (event, nested.as_deref_mut()) {
+            let info = match kind {
+                CodeBlockKind::Fenced(info) => CodeBlockInfo::parse(info),
+                CodeBlockKind::Indented => CodeBlockInfo::default(),
+            };
+            let mut code = String::new();
+            i += 1;
+            while let Some((Event::Text(text), _)) = events.get(i) {
+                code.push_str(text);
+                i += 1;
+            }
+            // Skip the block's end event
+            i += 1;
+            html.push_str(
+                &nested
+                    .render(&info, range.clone(), &code, false, options)
+                    .await?,
+            );
+            continue;
+        }
         match event {
             Event::Start(Tag::Paragraph) => {
                 let source_range = matching_end_range(events, i, TagEnd::Paragraph)
@@ -1341,7 +1833,7 @@ async fn render_events_to_html(
             }
             Event::Text(text) => {
                 if link_stack.is_empty() {
-                    render_text(html, text);
+                    render_text(html, text, options);
                 } else {
                     html.push_str(&html_escape(text));
                 }
@@ -1401,6 +1893,8 @@ async fn render_events_to_html(
             Event::Code(code) => {
                 html.push_str(&render_inline_code(
                     code,
+                    range,
+                    markdown,
                     options.inline_code_handler.as_ref(),
                 ));
             }
@@ -1412,6 +1906,7 @@ async fn render_events_to_html(
                     markdown,
                     &mut html_state,
                     source_map,
+                    glossary,
                 ) {
                     pulldown_cmark::html::push_html(html, std::iter::once(event.clone()));
                 }
@@ -1419,6 +1914,7 @@ async fn render_events_to_html(
         }
         i += 1;
     }
+    Ok(())
 }
 
 fn matching_end_range<'a>(
@@ -1442,6 +1938,7 @@ fn render_source_block_event(
     markdown: &str,
     state: &mut HtmlRenderState,
     source_map: &mut SourceMapBuilder,
+    glossary: &GlossaryCollector,
 ) -> bool {
     match event {
         Event::Start(Tag::BlockQuote(kind)) => {
@@ -1526,7 +2023,12 @@ fn render_source_block_event(
             let (sid, attrs) =
                 source_map.open_attr(SourceKind::DefinitionListTitle, range, markdown);
             state.definition_title = sid;
-            html.push_str(&format!("<dt{}>", attrs));
+            match glossary.anchor_at(range.start) {
+                Some(anchor_id) => {
+                    html.push_str(&format!("<dt id=\"{}\"{}>", html_escape(anchor_id), attrs))
+                }
+                None => html.push_str(&format!("<dt{}>", attrs)),
+            }
             true
         }
         Event::End(TagEnd::DefinitionListTitle) => {
@@ -1660,7 +2162,7 @@ async fn flush_req_text(
     html: &mut String,
     buffer: &mut String,
     marker_stripped: &mut bool,
-    _options: &RenderOptions,
+    options: &RenderOptions,
     render_wiki_links: bool,
 ) {
     if buffer.is_empty() {
@@ -1676,7 +2178,7 @@ async fn flush_req_text(
 
     if !text.is_empty() {
         if render_wiki_links {
-            render_text(html, &text);
+            render_text(html, &text, options);
         } else {
             html.push_str(&html_escape(&text));
         }
@@ -1691,13 +2193,14 @@ async fn flush_req_text(
 async fn render_paragraph_req_content(
     events: &[(Event<'_>, Range<usize>)],
     options: &RenderOptions,
+    markdown: &str,
 ) -> String {
     let mut html = String::new();
     let mut text_buffer = String::new();
     let mut marker_stripped = false;
     let mut link_stack: Vec<ActiveLink> = Vec::new();
 
-    for (event, _range) in events {
+    for (event, range) in events {
         match event {
             Event::Text(t) => {
                 text_buffer.push_str(t.as_ref());
@@ -1727,6 +2230,8 @@ async fn render_paragraph_req_content(
                 .await;
                 html.push_str(&render_inline_code(
                     code,
+                    range,
+                    markdown,
                     options.inline_code_handler.as_ref(),
                 ));
             }
@@ -1845,25 +2350,209 @@ async fn render_paragraph_req_content(
     html
 }
 
+/// Builds [`CodeBlockContext`]s for code blocks of the (possibly expanded)
+/// markdown, and locates positions in the files they came from.
+struct CodeContexts<'a> {
+    markdown: &'a str,
+    source_path: Option<&'a str>,
+    frontmatter: Option<&'a Frontmatter>,
+    include_map: Option<&'a IncludeMap>,
+}
+
+impl CodeContexts<'_> {
+    fn context<'c>(
+        &'c self,
+        info: &'c CodeBlockInfo,
+        range: Range<usize>,
+        in_req: bool,
+        in_blockquote: bool,
+    ) -> CodeBlockContext<'c> {
+        let origin = self.origin(range);
+
+        CodeBlockContext {
+            info,
+            // Blocks from the document itself keep the configured source path
+            source_path: origin.file.or(self.source_path),
+            line: origin.line,
+            span: origin.range,
+            frontmatter: self.frontmatter,
+            in_req,
+            in_blockquote,
+        }
+    }
+
+    /// Translate a range of the expanded markdown into the file it came from.
+    fn origin(&self, range: Range<usize>) -> Origin<'_> {
+        match self.include_map.and_then(|map| map.locate(range.clone())) {
+            Some(located) => Origin {
+                file: located.path,
+                line: located.line_start,
+                range: located.range,
+            },
+            None => Origin {
+                file: None,
+                line: offset_to_line(self.markdown, range.start),
+                range,
+            },
+        }
+    }
+
+    /// Move a requirement's positions from the expanded markdown into the
+    /// file it came from.
+    fn relocate_req(&self, req: &mut ReqDefinition) {
+        let span = self.origin(req.span.offset..req.span.offset + req.span.length);
+        let marker =
+            self.origin(req.marker_span.offset..req.marker_span.offset + req.marker_span.length);
+        req.span = SourceSpan {
+            offset: span.range.start,
+            length: span.range.len(),
+        };
+        req.marker_span = SourceSpan {
+            offset: marker.range.start,
+            length: marker.range.len(),
+        };
+        req.line = span.line;
+        req.file = span.file.map(str::to_owned);
+    }
+}
+
+/// A range of the expanded markdown, located in the file it came from.
+struct Origin<'a> {
+    /// Included file, or `None` for the document being rendered
+    file: Option<&'a str>,
+    /// 1-indexed line where the range starts
+    line: usize,
+    /// Byte range within `file`
+    range: Range<usize>,
+}
+
+/// Head injections and assets requested by code block handlers, deduplicated
+/// by key and by name.
+#[derive(Default)]
+struct HandlerResources {
+    head_injections: BTreeMap<String, String>,
+    assets: BTreeMap<String, StaticAsset>,
+}
+
+impl HandlerResources {
+    /// Keep a handler output's head injections and assets, returning its HTML.
+    fn keep(&mut self, output: CodeBlockOutput) -> String {
+        for injection in output.head_injections {
+            self.inject(injection);
+        }
+        for asset in output.assets {
+            self.assets.entry(asset.name.clone()).or_insert(asset);
+        }
+        output.html
+    }
+
+    fn inject(&mut self, injection: HeadInjection) {
+        self.head_injections
+            .entry(injection.key)
+            .or_insert(injection.html);
+    }
+}
+
+/// What rendering a code block inside a blockquote needs from the main loop.
+struct NestedCode<'r, 'c> {
+    contexts: &'r CodeContexts<'c>,
+    default_handler: &'r BoxedHandler,
+    diagnostics: &'r mut Vec<CodeBlockDiagnostic>,
+    resources: &'r mut HandlerResources,
+}
+
+impl NestedCode<'_, '_> {
+    /// Render a code block inside a blockquote (a requirement's, if `in_req`)
+    /// with its handler.
+    async fn render(
+        &mut self,
+        info: &CodeBlockInfo,
+        range: Range<usize>,
+        code: &str,
+        in_req: bool,
+        options: &RenderOptions,
+    ) -> Result<String> {
+        let handler = options
+            .code_handlers
+            .get(&info.language)
+            .or(options.default_handler.as_ref())
+            .unwrap_or(self.default_handler);
+        // Strip trailing newline from code
+        let code = code.trim_end_matches('\n');
+        let context = self.contexts.context(info, range, in_req, true);
+        let output = render_code_block(handler, &context, code, options, self.diagnostics).await?;
+        Ok(self.resources.keep(output))
+    }
+}
+
+/// Run a code block handler, applying the configured [`HandlerErrorPolicy`].
+async fn render_code_block(
+    handler: &BoxedHandler,
+    context: &CodeBlockContext<'_>,
+    code: &str,
+    options: &RenderOptions,
+    diagnostics: &mut Vec<CodeBlockDiagnostic>,
+) -> Result<CodeBlockOutput> {
+    let shown;
+    let code = if options.hide_rust_lines && doctest::is_rust(context.info) {
+        shown = doctest::strip_hidden_lines(code);
+        shown.as_str()
+    } else {
+        code
+    };
+
+    let error = match handler.render_with_context(context, code).await {
+        Ok(output) => return Ok(output),
+        Err(error) if options.handler_errors == HandlerErrorPolicy::Abort => return Err(error),
+        Err(error) => error,
+    };
+
+    let message = match error {
+        crate::Error::CodeBlockHandler { message, .. } => message,
+        other => other.to_string(),
+    };
+    let language = &context.info.language;
+    let html = format!(
+        "<div class=\"code-block code-error\" data-lang=\"{lang}\"><div class=\"code-error-message\" role=\"alert\">{location}: {message}</div><pre><code>{code}</code></pre></div>",
+        lang = html_escape(language),
+        location = html_escape(&context.location()),
+        message = html_escape(&message),
+        code = html_escape(code),
+    );
+
+    diagnostics.push(CodeBlockDiagnostic {
+        source_path: context.source_path.map(str::to_owned),
+        line: context.line,
+        span: SourceSpan {
+            offset: context.span.start,
+            length: context.span.len(),
+        },
+        language: language.clone(),
+        message,
+    });
+    Ok(html.into())
+}
+
 /// Render the content of a blockquote req (stripping blockquote wrapper and r[...] marker)
 ///
 /// Uses a text buffer to accumulate consecutive text events, then strips the req marker.
 async fn render_blockquote_req_content(
     events: &[(Event<'_>, Range<usize>)],
     options: &RenderOptions,
-    default_code_handler: &BoxedHandler,
+    nested: &mut NestedCode<'_, '_>,
 ) -> Result<String> {
     let mut html = String::new();
     let mut text_buffer = String::new();
     let mut marker_stripped = false;
     let mut in_paragraph = false;
     let mut in_code_block = false;
-    let mut code_block_lang = String::new();
+    let mut code_block_info = CodeBlockInfo::default();
+    let mut code_block_range = 0..0;
     let mut code_block_content = String::new();
     let mut blockquote_depth: usize = 0;
     let mut link_stack: Vec<ActiveLink> = Vec::new();
 
-    for (event, _range) in events {
+    for (event, range) in events {
         match event {
             Event::Start(Tag::BlockQuote(_)) => {
                 if blockquote_depth > 0 {
@@ -1919,25 +2608,25 @@ async fn render_blockquote_req_content(
                 )
                 .await;
                 in_code_block = true;
-                code_block_lang = match kind {
-                    CodeBlockKind::Fenced(lang) => lang.split(',').next().unwrap_or("").to_string(),
-                    CodeBlockKind::Indented => String::new(),
+                code_block_info = match kind {
+                    CodeBlockKind::Fenced(info) => CodeBlockInfo::parse(info),
+                    CodeBlockKind::Indented => CodeBlockInfo::default(),
                 };
+                code_block_range = range.clone();
                 code_block_content.clear();
             }
             Event::End(TagEnd::CodeBlock) => {
                 in_code_block = false;
-                let handler = options
-                    .code_handlers
-                    .get(&code_block_lang)
-                    .or(options.default_handler.as_ref())
-                    .unwrap_or(default_code_handler);
-                // Strip trailing newline from code
-                let code_trimmed = code_block_content.trim_end_matches('\n');
-                let output = handler.render(&code_block_lang, code_trimmed).await?;
-                // Head injections from blockquote code blocks are discarded here;
-                // the top-level render() call is responsible for collecting them.
-                html.push_str(&output.html);
+                let rendered = nested
+                    .render(
+                        &code_block_info,
+                        code_block_range.clone(),
+                        &code_block_content,
+                        true,
+                        options,
+                    )
+                    .await?;
+                html.push_str(&rendered);
             }
             Event::Text(t) if in_code_block => {
                 code_block_content.push_str(t);
@@ -1970,6 +2659,8 @@ async fn render_blockquote_req_content(
                 .await;
                 html.push_str(&render_inline_code(
                     code,
+                    range,
+                    nested.contexts.markdown,
                     options.inline_code_handler.as_ref(),
                 ));
             }
@@ -2139,6 +2830,7 @@ fn try_parse_paragraph_req<'a>(
         metadata,
         raw,
         html,
+        file: None,
     };
 
     Some(Ok(req))
@@ -2214,6 +2906,7 @@ fn try_parse_blockquote_req(
         metadata,
         raw,
         html,
+        file: None,
     };
 
     Some(Ok(req))
@@ -2245,6 +2938,7 @@ fn parse_req_leading_marker(text: &str) -> Option<(&str, &str, usize)> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::reqs::ReqWarningKind;
 
     struct TestWikiResolver;
 
@@ -2526,6 +3220,53 @@ fn main() {
         assert!(doc.html.contains("data-req=\"custom.test\""));
     }
 
+    #[tokio::test]
+    async fn test_req_prefixes() {
+        use crate::handler::ReqHandler;
+        use crate::reqs::ReqDefinition;
+        use std::future::Future;
+        use std::pin::Pin;
+
+        struct SectionHandler;
+
+        impl ReqHandler for SectionHandler {
+            fn start<'a>(
+                &'a self,
+                req: &'a ReqDefinition,
+            ) -> Pin<Box<dyn Future<Output = crate::Result<String>> + Send + 'a>> {
+                Box::pin(async move { Ok(format!("<section data-sec=\"{}\">", req.id)) })
+            }
+
+            fn end<'a>(
+                &'a self,
+                _req: &'a ReqDefinition,
+            ) -> Pin<Box<dyn Future<Output = crate::Result<String>> + Send + 'a>> {
+                Box::pin(async move { Ok("</section>".to_string()) })
+            }
+        }
+
+        let md = "r[a.one] The server MUST respond.\n\nsec[b.two] Section text.\n\nnote[1] Not a requirement.\n\n> a[i] Quoted, not a requirement.\n";
+
+        // Without declared prefixes every marker is a requirement
+        let doc = render(md, &RenderOptions::new()).await.unwrap();
+        assert_eq!(doc.reqs.len(), 4);
+
+        let opts = RenderOptions::new()
+            .with_req_prefix("r")
+            .with_req_prefix_handler("sec", SectionHandler);
+        let doc = render(md, &opts).await.unwrap();
+
+        let ids: Vec<String> = doc.reqs.iter().map(|r| r.id.to_string()).collect();
+        assert_eq!(ids, ["a.one", "b.two"]);
+        assert!(doc.html.contains("id=\"r-a.one\""));
+        assert!(
+            doc.html
+                .contains("<section data-sec=\"b.two\"><p>Section text.</p>\n</section>")
+        );
+        assert!(doc.html.contains("<p>note[1] Not a requirement.</p>"));
+        assert!(doc.html.contains("a[i] Quoted, not a requirement."));
+    }
+
     #[tokio::test]
     async fn test_render_hierarchical_heading_ids() {
         let md = r#"# Main Title
@@ -3595,26 +4336,126 @@ Third paragraph.
     }
 
     #[tokio::test]
-    async fn test_head_injections_collected() {
-        use crate::handlers::MermaidHandler;
+    async fn test_code_group_tabs_consecutive_blocks() {
+        let md = r#"```bash,group title="Bash"
+echo bash
+```
+```fish,group title="Fish"
+echo fish
+```
 
-        let md = "```mermaid\ngraph TD\n    A-->B\n```\n";
-        let opts = RenderOptions::new().with_handler(&["mermaid"], MermaidHandler::new());
-        let doc = render(md, &opts).await.unwrap();
+Between.
+
+```bash,group
+echo alone
+```
+```rust
+fn main() {}
+```
+"#;
+        let doc = render(md, &RenderOptions::new()).await.unwrap();
 
+        // The first two blocks form one group; the paragraph ends it
         assert_eq!(
-            doc.head_injections.len(),
-            1,
-            "Should have exactly one head injection"
+            doc.html
+                .matches(r#"<div class="code-group" data-tabs="code-group">"#)
+                .count(),
+            2
         );
         assert!(
-            doc.head_injections[0].contains("mermaid"),
-            "Head injection should contain mermaid script"
+            doc.html
+                .contains(r#"aria-selected="true" data-tab="Bash">Bash</button>"#)
         );
-    }
-
-    #[tokio::test]
-    async fn test_head_injections_deduplicated() {
+        assert!(
+            doc.html
+                .contains(r#"aria-selected="false" data-tab="Fish">Fish</button>"#)
+        );
+        assert!(doc.html.contains("echo fish"));
+        // An ungrouped block after a group is rendered on its own
+        let rust_block = doc.html.find("fn main").unwrap();
+        assert!(doc.html[..rust_block].ends_with(r#"<code class="language-rust">"#));
+        assert!(
+            doc.html[..rust_block].rfind("</div>\n").unwrap()
+                > doc.html.find("echo alone").unwrap()
+        );
+        // Every block is still its own code sample
+        assert_eq!(doc.code_samples.len(), 4);
+        assert_eq!(doc.head_injections.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rust_hidden_lines_and_doc_tests() {
+        let md = "```rust,no_run\n# fn main() {\nprintln!(\"hi\");\n# }\n```\n\n```text\n# heading\n```\n";
+        let opts = RenderOptions::new().with_hidden_rust_lines(true);
+        let doc = render(md, &opts).await.unwrap();
+
+        assert!(!doc.html.contains("fn main"));
+        assert!(doc.html.contains("println!"));
+        // Other languages keep their `# ` lines
+        assert!(doc.html.contains("# heading"));
+        assert!(doc.code_samples[0].code.contains("# fn main"));
+
+        let tests = doc.doc_tests();
+        assert_eq!(tests.len(), 1);
+        assert_eq!(tests[0].line, 1);
+        assert!(tests[0].no_run);
+        assert_eq!(tests[0].code, "fn main() {\nprintln!(\"hi\");\n}");
+
+        // Hidden lines are shown unless enabled
+        let doc = render(md, &RenderOptions::new()).await.unwrap();
+        assert!(doc.html.contains("# fn main"));
+    }
+
+    #[tokio::test]
+    async fn test_req_warnings() {
+        let md = "# Spec\n\nr[a.ok]\nThe server MUST respond.\n\nr[a.vague]\nThe server responds.\n\n> r[a.neg]\n> It MUST ack and\n> MUST NOT retry.\n";
+        let opts = RenderOptions::new().with_source_path("spec.md");
+        let doc = render(md, &opts).await.unwrap();
+
+        assert_eq!(doc.req_warnings.len(), 2);
+
+        let vague = &doc.req_warnings[0];
+        assert_eq!(vague.req_id.base, "a.vague");
+        assert_eq!(vague.kind, ReqWarningKind::NoRfc2119Keyword);
+        assert_eq!(vague.line, 6);
+        assert_eq!(vague.file.to_str(), Some("spec.md"));
+        let req = doc.reqs.iter().find(|r| r.id.base == "a.vague").unwrap();
+        assert_eq!(vague.span, req.span);
+
+        let neg = &doc.req_warnings[1];
+        assert_eq!(neg.req_id.base, "a.neg");
+        assert_eq!(
+            neg.kind,
+            ReqWarningKind::NegativeReq(crate::Rfc2119Keyword::MustNot)
+        );
+        assert_eq!(neg.line, 11);
+        assert_eq!(
+            &md[neg.span.offset..neg.span.offset + neg.span.length],
+            "MUST NOT"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_head_injections_collected() {
+        use crate::handlers::MermaidHandler;
+
+        let md = "```mermaid\ngraph TD\n    A-->B\n```\n";
+        let opts = RenderOptions::new().with_handler(&["mermaid"], MermaidHandler::new());
+        let doc = render(md, &opts).await.unwrap();
+
+        assert_eq!(
+            doc.head_injections.len(),
+            1,
+            "Should have exactly one head injection"
+        );
+        assert!(
+            doc.head_injections[0].contains("mermaid"),
+            "Head injection should contain mermaid script"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_head_injections_deduplicated() {
         use crate::handlers::MermaidHandler;
 
         let md = "```mermaid\ngraph TD\n    A-->B\n```\n\n```mermaid\ngraph LR\n    X-->Y\n```\n";
@@ -3629,6 +4470,44 @@ Third paragraph.
         );
     }
 
+    #[tokio::test]
+    async fn test_assets_collected_and_deduplicated() {
+        use crate::handlers::MermaidHandler;
+
+        let md = "```mermaid\ngraph TD\n```\n\n```mermaid\ngraph LR\n```\n";
+        let handler = MermaidHandler::new()
+            .with_module_url("/assets/mermaid.mjs")
+            .with_asset(StaticAsset::new(
+                "mermaid.mjs",
+                "text/javascript",
+                b"x".to_vec(),
+            ));
+        let opts = RenderOptions::new().with_handler(&["mermaid"], handler);
+        let doc = render(md, &opts).await.unwrap();
+
+        assert_eq!(doc.assets.len(), 1);
+        assert_eq!(doc.assets[0].name, "mermaid.mjs");
+        assert!(doc.head_injections[0].contains("/assets/mermaid.mjs"));
+    }
+
+    #[tokio::test]
+    async fn test_assets_collected_from_req_blockquotes() {
+        use crate::handlers::MermaidHandler;
+
+        let md = "> r[a.b]\n> Flow:\n>\n> ```mermaid\n> graph TD\n> ```\n";
+        let handler = MermaidHandler::new().with_asset(StaticAsset::new(
+            "mermaid.mjs",
+            "text/javascript",
+            b"x".to_vec(),
+        ));
+        let opts = RenderOptions::new().with_handler(&["mermaid"], handler);
+        let doc = render(md, &opts).await.unwrap();
+
+        assert_eq!(doc.reqs.len(), 1);
+        assert_eq!(doc.assets.len(), 1);
+        assert_eq!(doc.head_injections.len(), 1);
+    }
+
     #[tokio::test]
     async fn test_req_in_blockquote_with_nested_blockquote() {
         let md = "> r[my.rule]\n> > quoted text";
@@ -3768,4 +4647,641 @@ Third paragraph.
         assert_eq!(doc.inline_code_spans.len(), 1);
         assert_eq!(doc.inline_code_spans[0].content, "r[auth.login]");
     }
+
+    #[tokio::test]
+    async fn test_definition_list_terms_collected_into_glossary() {
+        let md = "Source map\n: Maps rendered HTML back to `markdown`.\n  Opt-in.\n\n**Rule**\n: A requirement.\n";
+        let doc = render(md, &RenderOptions::default()).await.unwrap();
+
+        assert_eq!(doc.glossary.len(), 2);
+        assert_eq!(doc.glossary[0].term, "Source map");
+        assert_eq!(doc.glossary[0].anchor_id, "glossary-source-map");
+        assert_eq!(
+            doc.glossary[0].definition,
+            "Maps rendered HTML back to markdown. Opt-in."
+        );
+        assert_eq!(doc.glossary[0].line, 1);
+        assert_eq!(doc.glossary[1].term, "Rule");
+        assert_eq!(doc.glossary[1].line, 5);
+        assert!(
+            doc.html
+                .contains(r#"<dt id="glossary-source-map">Source map</dt>"#),
+            "dt should carry the glossary anchor: {}",
+            doc.html
+        );
+        assert!(
+            doc.html
+                .contains(r#"<dt id="glossary-rule"><strong>Rule</strong></dt>"#),
+            "dt should carry the glossary anchor: {}",
+            doc.html
+        );
+    }
+
+    #[tokio::test]
+    async fn test_glossary_anchor_only_on_first_definition() {
+        let md = "Rule\n: A requirement.\n\nrule\n: Again.\n\n> Source map\n> : Maps.\n\nSource-map\n: Maps again.\n";
+        let doc = render(md, &RenderOptions::default()).await.unwrap();
+
+        assert_eq!(doc.glossary.len(), 2);
+        assert_eq!(doc.glossary[0].definition, "A requirement.");
+        assert_eq!(
+            doc.html.matches(r#"id="glossary-rule""#).count(),
+            1,
+            "{}",
+            doc.html
+        );
+        assert_eq!(
+            doc.html.matches(r#"id="glossary-source-map""#).count(),
+            1,
+            "{}",
+            doc.html
+        );
+        assert!(doc.html.contains("<dt>rule</dt>"), "{}", doc.html);
+        assert!(
+            doc.html
+                .contains(r#"<dt id="glossary-source-map">Source map</dt>"#),
+            "{}",
+            doc.html
+        );
+    }
+
+    #[tokio::test]
+    async fn test_glossary_terms_are_not_linked_by_default() {
+        let md = "Every rule matters.\n\nRule\n: A requirement.\n";
+        let doc = render(md, &RenderOptions::default()).await.unwrap();
+
+        assert!(!doc.html.contains("glossary-link"), "{}", doc.html);
+    }
+
+    #[tokio::test]
+    async fn test_glossary_links_first_occurrence() {
+        let md = "# Rule\n\nA `rule` is a rule, and another rule.\n\nRule\n: A requirement.\n";
+        let opts = RenderOptions::new().with_glossary_links(true);
+        let doc = render(md, &opts).await.unwrap();
+
+        assert_eq!(
+            doc.html.matches("glossary-link").count(),
+            1,
+            "only the first prose occurrence should be linked: {}",
+            doc.html
+        );
+        assert!(
+            doc.html.contains(
+                r##"is a <a class="glossary-link" href="#glossary-rule"><abbr title="A requirement.">rule</abbr></a>, and"##
+            ),
+            "{}",
+            doc.html
+        );
+    }
+
+    #[tokio::test]
+    async fn test_external_glossary_links() {
+        let md = "Check the source map first.";
+        let opts = RenderOptions::new()
+            .with_glossary_links(true)
+            .with_glossary([GlossaryEntry::external(
+                "source map",
+                "/glossary/#glossary-source-map",
+                "Maps HTML back to markdown.",
+            )]);
+        let doc = render(md, &opts).await.unwrap();
+
+        assert!(doc.glossary.is_empty());
+        assert!(
+            doc.html
+                .contains(r#"<a class="glossary-link" href="/glossary/#glossary-source-map">"#),
+            "{}",
+            doc.html
+        );
+    }
+
+    #[cfg(feature = "emoji")]
+    #[tokio::test]
+    async fn test_emoji_shortcodes() {
+        let md = "r[ship.it]\nShip it :rocket: with `:rocket:` and [:rocket:](https://example.com/:rocket:).\n\n```\n:rocket:\n```\n";
+        let opts = RenderOptions::new().with_emoji_shortcodes(true);
+        let doc = render(md, &opts).await.unwrap();
+
+        assert!(doc.html.contains("Ship it 🚀 with"), "{}", doc.html);
+        assert!(doc.html.contains("<code>:rocket:</code>"), "{}", doc.html);
+        assert!(
+            doc.html.contains(r#"href="https://example.com/:rocket:""#),
+            "{}",
+            doc.html
+        );
+        assert_eq!(doc.html.matches('🚀').count(), 1, "{}", doc.html);
+
+        let plain = render(md, &RenderOptions::new()).await.unwrap();
+        assert!(!plain.html.contains('🚀'));
+    }
+
+    #[tokio::test]
+    async fn test_typography_locale() {
+        let md = "Il a dit \"bonjour\" : c'est `a - b` ?";
+        let opts = RenderOptions::new().with_typography(true).with_lang("fr");
+        let doc = render(md, &opts).await.unwrap();
+
+        assert!(
+            doc.html.contains(
+                "Il\u{a0}a\u{a0}dit «\u{a0}bonjour\u{a0}»\u{a0}: c’est <code>a - b</code>\u{202f}?"
+            ),
+            "{}",
+            doc.html
+        );
+
+        let plain = render(md, &RenderOptions::new()).await.unwrap();
+        assert!(
+            plain.html.contains("dit &quot;bonjour&quot; : c&#x27;est"),
+            "{}",
+            plain.html
+        );
+    }
+
+    #[tokio::test]
+    async fn test_typography_lang_from_frontmatter() {
+        let md = "+++\ntitle = \"Hallo\"\n[extra]\nlang = \"de\"\n+++\nEr sagte \"Hallo\".";
+        let opts = RenderOptions::new().with_typography(true).with_lang("fr");
+        let doc = render(md, &opts).await.unwrap();
+
+        assert!(doc.html.contains("Er\u{a0}sagte „Hallo“."), "{}", doc.html);
+    }
+
+    use crate::handler::IncludedFile;
+
+    struct TestIncludeResolver(HashMap<&'static str, &'static str>);
+
+    impl IncludeResolver for TestIncludeResolver {
+        fn resolve<'a>(
+            &'a self,
+            path: &'a str,
+            _source_path: Option<&'a str>,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<IncludedFile>> + Send + 'a>>
+        {
+            Box::pin(async move {
+                let content = self.0.get(path).ok_or_else(|| crate::Error::Include {
+                    path: path.to_string(),
+                    message: "not found".to_string(),
+                })?;
+                Ok(IncludedFile::new(path, *content))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_include_directives() {
+        let resolver = TestIncludeResolver(HashMap::from([
+            (
+                "license.md",
+                "+++\ntitle = \"License\"\n+++\n# License\n\nShared text.\n",
+            ),
+            ("nested.md", "## Nested\n\n{{#include license.md}}\n"),
+        ]));
+        let md = "# Spec\n\n<!-- include nested.md shift=1 -->\n\nAfter.\n\n```\n{{#include license.md}}\n```\n";
+        let opts = RenderOptions::new()
+            .with_include_resolver(resolver)
+            .with_source_map(true);
+        let doc = render(md, &opts).await.unwrap();
+
+        let levels: Vec<_> = doc
+            .headings
+            .iter()
+            .map(|h| (h.title.as_str(), h.level))
+            .collect();
+        assert_eq!(levels, [("Spec", 1), ("Nested", 3), ("License", 2)]);
+        assert!(doc.html.contains("{{#include license.md}}"), "{}", doc.html);
+        assert_eq!(doc.includes, ["nested.md", "license.md"]);
+
+        let shared = doc
+            .source_map
+            .entries
+            .iter()
+            .find(|entry| entry.kind == SourceKind::Paragraph)
+            .unwrap();
+        assert_eq!(shared.file.as_deref(), Some("license.md"));
+        assert_eq!((shared.line_start, shared.line_end), (6, 6));
+
+        let after = doc
+            .source_map
+            .entries
+            .iter()
+            .filter(|entry| entry.kind == SourceKind::Paragraph)
+            .nth(1)
+            .unwrap();
+        assert_eq!(after.file, None);
+        assert_eq!(after.line_start, 5);
+        assert_eq!(&md[after.byte_start..after.byte_end], "After.\n");
+    }
+
+    #[tokio::test]
+    async fn test_include_cycle_is_an_error() {
+        let resolver = TestIncludeResolver(HashMap::from([
+            ("a.md", "<!-- include b.md -->\n"),
+            ("b.md", "<!-- include a.md -->\n"),
+        ]));
+        let opts = RenderOptions::new().with_include_resolver(resolver);
+        let err = render("<!-- include a.md -->\n", &opts).await.unwrap_err();
+
+        assert!(
+            err.to_string()
+                .contains("include cycle: a.md -> b.md -> a.md"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_self_include_is_an_error() {
+        let resolver = TestIncludeResolver(HashMap::from([("docs/spec.md", "Spec text.\n")]));
+        let opts = RenderOptions::new()
+            .with_source_path("docs/spec.md")
+            .with_include_resolver(resolver);
+        let err = render("Intro\n\n<!-- include docs/spec.md -->\n", &opts)
+            .await
+            .unwrap_err();
+
+        assert!(
+            err.to_string()
+                .contains("include cycle: docs/spec.md -> docs/spec.md"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_include_positions() {
+        let included =
+            "# Included\n\nr[a.req] It MUST work.\n\n```sh\necho hi\n```\n\nText with `code`.\n";
+        let resolver = TestIncludeResolver(HashMap::from([("a.md", included)]));
+        let md = "intro\n\n<!-- include a.md -->\n\nr[x.y] The server MUST use `tls`.\n\n```rust\nlet x = 1;\n```\n";
+        let opts = RenderOptions::new().with_include_resolver(resolver);
+        let doc = render(md, &opts).await.unwrap();
+
+        let span = |source: &'static str, span: SourceSpan| {
+            &source[span.offset..span.offset + span.length]
+        };
+
+        let included_req = &doc.reqs[0];
+        assert_eq!(included_req.file.as_deref(), Some("a.md"));
+        assert_eq!(included_req.line, 3);
+        assert_eq!(span(included, included_req.marker_span), "r[a.req]");
+        assert_eq!(span(included, included_req.span), "r[a.req] It MUST work.");
+
+        let req = &doc.reqs[1];
+        assert_eq!(req.file, None);
+        assert_eq!(req.line, 5);
+        assert_eq!(span(md, req.marker_span), "r[x.y]");
+
+        let samples: Vec<_> = doc
+            .code_samples
+            .iter()
+            .map(|sample| (sample.file.as_deref(), sample.line))
+            .collect();
+        assert_eq!(samples, [(Some("a.md"), 5), (None, 7)]);
+
+        let spans: Vec<_> = doc
+            .inline_code_spans
+            .iter()
+            .map(|code| match code.file.as_deref() {
+                Some("a.md") => span(included, code.span),
+                _ => span(md, code.span),
+            })
+            .collect();
+        assert_eq!(spans, ["`code`", "`tls`"]);
+
+        assert_eq!(doc.headings[0].file.as_deref(), Some("a.md"));
+        assert_eq!(doc.headings[0].line, 1);
+
+        let paragraphs: Vec<_> = doc
+            .elements
+            .iter()
+            .filter_map(|element| match element {
+                DocElement::Paragraph(p) => Some((p.file.as_deref(), p.line, p.offset)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(paragraphs, [(None, 1, 0), (Some("a.md"), 9, 55)]);
+        assert!(included[55..].starts_with("Text with"));
+        assert!(matches!(&doc.elements[2], DocElement::Req(r) if r == included_req));
+    }
+
+    #[tokio::test]
+    async fn test_doc_tests_from_includes() {
+        let resolver = TestIncludeResolver(HashMap::from([(
+            "guide/a.md",
+            "```rust\nlet a = 1;\n```\n",
+        )]));
+        let md = "```rust\nlet x = 1;\n```\n\n<!-- include guide/a.md -->\n";
+        let opts = RenderOptions::new().with_include_resolver(resolver);
+        let doc = render(md, &opts).await.unwrap();
+
+        let tests = doc.doc_tests();
+        let names: Vec<_> = tests.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["line_1", "line_1_guide_a_md"]);
+        assert_eq!(tests[1].file.as_deref(), Some("guide/a.md"));
+
+        let file = crate::doc_test_file("main.md", &tests);
+        assert!(file.contains("/// `main.md` line 1\n"), "{file}");
+        assert!(file.contains("/// `guide/a.md` line 1\n"), "{file}");
+        assert!(file.contains("pub mod line_1_guide_a_md {}"), "{file}");
+    }
+
+    #[tokio::test]
+    async fn test_req_warnings_after_include() {
+        let included = "Shared intro.\n\nMore text.\n\nr[b.neg]\nClients MUST NOT retry.\n";
+        let resolver = TestIncludeResolver(HashMap::from([("b.md", included)]));
+        let md = "# Spec\n\n<!-- include b.md -->\n\nr[a.vague]\nThe server responds.\n";
+        let opts = RenderOptions::new()
+            .with_source_path("spec.md")
+            .with_include_resolver(resolver);
+        let doc = render(md, &opts).await.unwrap();
+
+        assert_eq!(doc.req_warnings.len(), 2);
+
+        // The keyword is on the line after the included requirement's marker
+        let neg = &doc.req_warnings[0];
+        let req = doc.reqs.iter().find(|r| r.id.base == "b.neg").unwrap();
+        assert_eq!(neg.file.to_str(), Some("b.md"));
+        assert_eq!(req.file.as_deref(), Some("b.md"));
+        assert_eq!((neg.line, req.line), (6, 5));
+        assert_eq!(
+            &included[neg.span.offset..neg.span.offset + neg.span.length],
+            "MUST NOT"
+        );
+
+        let vague = &doc.req_warnings[1];
+        let req = doc.reqs.iter().find(|r| r.id.base == "a.vague").unwrap();
+        assert_eq!(vague.file.to_str(), Some("spec.md"));
+        assert_eq!((vague.line, vague.span), (req.line, req.span));
+        assert_eq!(vague.line, 5);
+    }
+
+    #[tokio::test]
+    async fn test_variable_substitution() {
+        let md = "Install {{ version }} from [the docs](https://docs.rs/marq/{{version}}).\n\n```sh\ncargo add marq@{{ version }}\n```\n\nLiteral `{{ version }}`.\n";
+        let opts = RenderOptions::new()
+            .with_variable_substitution(true)
+            .with_variable("version", "0.3.1");
+        let doc = render(md, &opts).await.unwrap();
+
+        assert!(doc.html.contains("Install 0.3.1 from"), "{}", doc.html);
+        assert!(
+            doc.html.contains(r#"href="https://docs.rs/marq/0.3.1""#),
+            "{}",
+            doc.html
+        );
+        assert!(
+            doc.html.contains("cargo add marq@{{ version }}"),
+            "{}",
+            doc.html
+        );
+        assert!(
+            doc.html.contains("<code>{{ version }}</code>"),
+            "{}",
+            doc.html
+        );
+
+        let opts = opts.with_variables_in_code(true);
+        let doc = render(md, &opts).await.unwrap();
+        assert!(doc.html.contains("cargo add marq@0.3.1"), "{}", doc.html);
+        assert!(doc.html.contains("<code>0.3.1</code>"), "{}", doc.html);
+    }
+
+    #[tokio::test]
+    async fn test_variable_substitution_from_frontmatter() {
+        let md = "+++\ntitle = \"Install\"\n[extra]\nrust_version = \"1.85\"\n+++\n{{ page.title }} needs Rust {{ extra.rust_version }}.";
+        let opts = RenderOptions::new().with_variable_substitution(true);
+        let doc = render(md, &opts).await.unwrap();
+
+        assert!(
+            doc.html.contains("Install needs Rust 1.85."),
+            "{}",
+            doc.html
+        );
+    }
+
+    struct InfoEchoHandler;
+
+    impl CodeBlockHandler for InfoEchoHandler {
+        fn render<'a>(
+            &'a self,
+            language: &'a str,
+            _code: &'a str,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<CodeBlockOutput>> + Send + 'a>>
+        {
+            Box::pin(async move { Ok(format!("<lang>{language}</lang>").into()) })
+        }
+
+        fn render_with_info<'a>(
+            &'a self,
+            info: &'a CodeBlockInfo,
+            _code: &'a str,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<CodeBlockOutput>> + Send + 'a>>
+        {
+            Box::pin(async move {
+                Ok(format!(
+                    "<info title=\"{}\" flags=\"{}\" lines=\"{:?}\"></info>",
+                    info.attr("title").unwrap_or_default(),
+                    info.flags.join(","),
+                    info.highlight_lines
+                )
+                .into())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_code_block_info_passed_to_handlers() {
+        let md = "```rust,ignore title=\"main.rs\" {3-5}\nfn main() {}\n```\n\n> r[code.quoted]\n> Quoted code:\n>\n> ```rust,no_run {2}\n> fn quoted() {}\n> ```\n";
+        let opts = RenderOptions::new().with_handler(&["rust"], InfoEchoHandler);
+        let doc = render(md, &opts).await.unwrap();
+
+        assert!(
+            doc.html
+                .contains(r#"<info title="main.rs" flags="ignore" lines="[3..=5]"></info>"#),
+            "{}",
+            doc.html
+        );
+        assert!(
+            doc.html
+                .contains(r#"<info title="" flags="no_run" lines="[2..=2]"></info>"#),
+            "{}",
+            doc.html
+        );
+
+        let sample = &doc.code_samples[0];
+        assert_eq!(sample.language, "rust,ignore");
+        assert_eq!(sample.info.language, "rust");
+        assert_eq!(sample.info.attr("title"), Some("main.rs"));
+    }
+
+    struct ContextEchoHandler;
+
+    impl CodeBlockHandler for ContextEchoHandler {
+        fn render<'a>(
+            &'a self,
+            _language: &'a str,
+            _code: &'a str,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<CodeBlockOutput>> + Send + 'a>>
+        {
+            Box::pin(async move { Ok(String::new().into()) })
+        }
+
+        fn render_with_context<'a>(
+            &'a self,
+            context: &'a CodeBlockContext<'a>,
+            _code: &'a str,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<CodeBlockOutput>> + Send + 'a>>
+        {
+            Box::pin(async move {
+                Ok(format!(
+                    "<ctx at=\"{}\" span=\"{:?}\" req=\"{}\" quote=\"{}\"></ctx>",
+                    context.location(),
+                    context.span,
+                    context.in_req,
+                    context.in_blockquote
+                )
+                .into())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_code_block_context_passed_to_handlers() {
+        let resolver = TestIncludeResolver(HashMap::from([(
+            "snippet.md",
+            "Snippet:\n\n```pik\nbox\n```\n",
+        )]));
+        let md = "Intro\n\n```pik\ncircle\n```\n\n{{#include snippet.md}}\n\n> r[pic.quoted]\n> Quoted:\n>\n> ```pik\n> arrow\n> ```\n\n> Plain:\n>\n> ```pik\n> line\n> ```\n";
+        let opts = RenderOptions::new()
+            .with_source_path("docs/spec.md")
+            .with_include_resolver(resolver)
+            .with_handler(&["pik"], ContextEchoHandler);
+        let doc = render(md, &opts).await.unwrap();
+
+        assert!(
+            doc.html.contains(
+                r#"<ctx at="docs/spec.md:3" span="7..24" req="false" quote="false"></ctx>"#
+            ),
+            "{}",
+            doc.html
+        );
+        assert!(
+            doc.html.contains(
+                r#"<ctx at="snippet.md:3" span="10..24" req="false" quote="false"></ctx>"#
+            ),
+            "{}",
+            doc.html
+        );
+        assert!(
+            doc.html.contains(
+                r#"<ctx at="docs/spec.md:12" span="81..101" req="true" quote="true"></ctx>"#
+            ),
+            "{}",
+            doc.html
+        );
+        assert!(
+            doc.html.contains(
+                r#"<ctx at="docs/spec.md:18" span="116..135" req="false" quote="true"></ctx>"#
+            ),
+            "{}",
+            doc.html
+        );
+    }
+
+    struct FailingHandler;
+
+    impl CodeBlockHandler for FailingHandler {
+        fn render<'a>(
+            &'a self,
+            language: &'a str,
+            _code: &'a str,
+        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<CodeBlockOutput>> + Send + 'a>>
+        {
+            Box::pin(async move {
+                Err(crate::Error::CodeBlockHandler {
+                    language: language.to_string(),
+                    message: "unexpected token".to_string(),
+                })
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handler_error_policy() {
+        let md = "# Diagram\n\n```pik\nbox <oops>\n```\n\nStill rendered.\n";
+        let opts = RenderOptions::new()
+            .with_source_path("docs/pic.md")
+            .with_handler(&["pik"], FailingHandler);
+
+        assert!(matches!(
+            render(md, &opts).await,
+            Err(crate::Error::CodeBlockHandler { .. })
+        ));
+
+        let opts = opts.with_handler_error_policy(HandlerErrorPolicy::Inline);
+        let doc = render(md, &opts).await.unwrap();
+        assert!(
+            doc.html.contains(
+                "<div class=\"code-block code-error\" data-lang=\"pik\"><div class=\"code-error-message\" role=\"alert\">docs/pic.md:3: unexpected token</div><pre><code>box &lt;oops&gt;</code></pre></div>"
+            ),
+            "{}",
+            doc.html
+        );
+        assert!(doc.html.contains("Still rendered."), "{}", doc.html);
+        assert_eq!(
+            doc.diagnostics,
+            [CodeBlockDiagnostic {
+                source_path: Some("docs/pic.md".to_string()),
+                line: 3,
+                span: SourceSpan {
+                    offset: 11,
+                    length: 21,
+                },
+                language: "pik".to_string(),
+                message: "unexpected token".to_string(),
+            }]
+        );
+    }
+
+    struct LanguageEchoInlineHandler;
+
+    impl InlineCodeHandler for LanguageEchoInlineHandler {
+        fn render(&self, _code: &str) -> Option<String> {
+            None
+        }
+
+        fn render_with_language(&self, code: &str, language: Option<&str>) -> Option<String> {
+            language.map(|language| format!("<code data-lang=\"{language}\">{code}</code>"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_inline_code_language_attribute() {
+        let md = "Use `let x = 1`{.rust} or `x`{.py}, not `y` {.js}.\n\n> r[inline.lang]\n> Call `f()`{.rust}.\n";
+
+        let doc = render(md, &RenderOptions::default()).await.unwrap();
+        assert!(
+            doc.html.contains("<code>let x = 1</code>{.rust}"),
+            "{}",
+            doc.html
+        );
+
+        let opts = RenderOptions {
+            inline_code_handler: Some(Arc::new(LanguageEchoInlineHandler)),
+            ..Default::default()
+        };
+        let doc = render(md, &opts).await.unwrap();
+        assert!(
+            doc.html.contains(
+                "Use <code data-lang=\"rust\">let x = 1</code> or <code data-lang=\"py\">x</code>, not <code>y</code> {.js}."
+            ),
+            "{}",
+            doc.html
+        );
+        assert!(
+            doc.html
+                .contains("Call <code data-lang=\"rust\">f()</code>."),
+            "{}",
+            doc.html
+        );
+        assert_eq!(doc.inline_code_spans.len(), 4);
+    }
 }
@@ -2,6 +2,7 @@
 //!
 //! Supports the req id syntax used by tracey, see <https://github.com/bearcove/tracey>
 
+use std::ops::Range;
 use std::path::PathBuf;
 
 use facet::Facet;
@@ -24,6 +25,9 @@ pub struct InlineCodeSpan {
     pub content: String,
     /// Source span covering the entire code span including backtick delimiters
     pub span: SourceSpan,
+    /// Included file this code span came from, or `None` for the rendered
+    /// document. The span is relative to this file.
+    pub file: Option<String>,
 }
 
 /// Structured rule identifier with optional version.
@@ -124,39 +128,61 @@ impl Rfc2119Keyword {
 /// Returns all keywords found, checking for negative forms first.
 /// Keywords must be uppercase to match RFC 2119 conventions.
 pub fn detect_rfc2119_keywords(text: &str) -> Vec<Rfc2119Keyword> {
+    detect_rfc2119_keyword_spans(text)
+        .into_iter()
+        .map(|(keyword, _)| keyword)
+        .collect()
+}
+
+/// Like [`detect_rfc2119_keywords`], with the byte range of each keyword in `text`.
+pub(crate) fn detect_rfc2119_keyword_spans(text: &str) -> Vec<(Rfc2119Keyword, Range<usize>)> {
     let mut keywords = Vec::new();
-    let words: Vec<&str> = text.split_whitespace().collect();
+    let words: Vec<(&str, Range<usize>)> = text
+        .split_whitespace()
+        .map(|word| {
+            let start = word.as_ptr() as usize - text.as_ptr() as usize;
+            let trimmed = word.trim_matches(|c: char| !c.is_alphanumeric());
+            let start = start + word.len()
+                - word
+                    .trim_start_matches(|c: char| !c.is_alphanumeric())
+                    .len();
+            (trimmed, start..start + trimmed.len())
+        })
+        .collect();
 
     let mut i = 0;
     while i < words.len() {
-        let word = words[i].trim_matches(|c: char| !c.is_alphanumeric());
+        let (word, ref range) = words[i];
 
         // Check for two-word negative forms
-        if i + 1 < words.len() {
-            let next_word = words[i + 1].trim_matches(|c: char| !c.is_alphanumeric());
-            if (word == "MUST" || word == "SHALL") && next_word == "NOT" {
-                keywords.push(Rfc2119Keyword::MustNot);
+        if let Some((next_word, next_range)) = words.get(i + 1) {
+            let both = range.start..next_range.end;
+            if (word == "MUST" || word == "SHALL") && *next_word == "NOT" {
+                keywords.push((Rfc2119Keyword::MustNot, both));
                 i += 2;
                 continue;
             }
-            if word == "SHOULD" && next_word == "NOT" {
-                keywords.push(Rfc2119Keyword::ShouldNot);
+            if word == "SHOULD" && *next_word == "NOT" {
+                keywords.push((Rfc2119Keyword::ShouldNot, both));
                 i += 2;
                 continue;
             }
-            if word == "NOT" && next_word == "RECOMMENDED" {
-                keywords.push(Rfc2119Keyword::ShouldNot);
+            if word == "NOT" && *next_word == "RECOMMENDED" {
+                keywords.push((Rfc2119Keyword::ShouldNot, both));
                 i += 2;
                 continue;
             }
         }
 
         // Check single-word forms
-        match word {
-            "MUST" | "SHALL" | "REQUIRED" => keywords.push(Rfc2119Keyword::Must),
-            "SHOULD" | "RECOMMENDED" => keywords.push(Rfc2119Keyword::Should),
-            "MAY" | "OPTIONAL" => keywords.push(Rfc2119Keyword::May),
-            _ => {}
+        let keyword = match word {
+            "MUST" | "SHALL" | "REQUIRED" => Some(Rfc2119Keyword::Must),
+            "SHOULD" | "RECOMMENDED" => Some(Rfc2119Keyword::Should),
+            "MAY" | "OPTIONAL" => Some(Rfc2119Keyword::May),
+            _ => None,
+        };
+        if let Some(keyword) = keyword {
+            keywords.push((keyword, range.clone()));
         }
         i += 1;
     }
@@ -164,6 +190,21 @@ pub fn detect_rfc2119_keywords(text: &str) -> Vec<Rfc2119Keyword> {
     keywords
 }
 
+/// Find quality problems in a requirement's content, given the source text
+/// after its marker. Spans are relative to `content`; a missing keyword has
+/// no span of its own.
+pub(crate) fn analyze_req_content(content: &str) -> Vec<(ReqWarningKind, Option<Range<usize>>)> {
+    let keywords = detect_rfc2119_keyword_spans(content);
+    if keywords.is_empty() {
+        return vec![(ReqWarningKind::NoRfc2119Keyword, None)];
+    }
+    keywords
+        .into_iter()
+        .filter(|(keyword, _)| keyword.is_negative())
+        .map(|(keyword, range)| (ReqWarningKind::NegativeReq(keyword), Some(range)))
+        .collect()
+}
+
 /// Lifecycle status of a requirement.
 ///
 /// Requirements progress through these states as the specification evolves.
@@ -312,6 +353,9 @@ pub struct ReqDefinition {
     pub raw: String,
     /// The rendered HTML of the content following the requirement marker
     pub html: String,
+    /// Included file this requirement came from, or `None` for the rendered
+    /// document. The line and spans are relative to this file.
+    pub file: Option<String>,
 }
 
 /// Warning about requirement quality.
@@ -330,7 +374,7 @@ pub struct ReqWarning {
 }
 
 /// Types of requirement warnings.
-#[derive(Debug, Clone, Facet)]
+#[derive(Debug, Clone, PartialEq, Eq, Facet)]
 #[repr(u8)]
 pub enum ReqWarningKind {
     /// Requirement text contains no RFC 2119 keywords
@@ -481,6 +525,40 @@ mod tests {
         assert!(keywords.is_empty());
     }
 
+    #[test]
+    fn test_detect_rfc2119_spans() {
+        let text = "It MUST respond; it SHALL  NOT (crash) and\n**SHOULD** log.";
+        let spans = detect_rfc2119_keyword_spans(text);
+        let found: Vec<_> = spans
+            .iter()
+            .map(|(keyword, range)| (*keyword, &text[range.clone()]))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (Rfc2119Keyword::Must, "MUST"),
+                (Rfc2119Keyword::MustNot, "SHALL  NOT"),
+                (Rfc2119Keyword::Should, "SHOULD"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_req_content() {
+        assert_eq!(
+            analyze_req_content("The server responds."),
+            [(ReqWarningKind::NoRfc2119Keyword, None)]
+        );
+        assert_eq!(analyze_req_content("The server MUST respond."), []);
+        assert_eq!(
+            analyze_req_content("It MUST ack and MUST NOT retry."),
+            [(
+                ReqWarningKind::NegativeReq(Rfc2119Keyword::MustNot),
+                Some(16..24)
+            )]
+        );
+    }
+
     // Metadata coverage tests
 
     #[test]
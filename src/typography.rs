@@ -0,0 +1,245 @@
+//! Locale-aware typography for prose text.
+//!
+//! Builds on pulldown-cmark's smart punctuation (curly quotes, `…`, `–`, `—`),
+//! which is enabled whenever typography is, and adds the rules that depend on
+//! the document language: quote styles, French spacing before high
+//! punctuation, and non-breaking spaces before units and after short words.
+
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+use regex::{Captures, Regex};
+
+const NBSP: char = '\u{a0}';
+const NARROW_NBSP: char = '\u{202f}';
+
+/// Typography rules for a document language.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Locale {
+    English,
+    French,
+    German,
+    /// Any other language: only locale-independent rules apply
+    Other,
+}
+
+impl Locale {
+    /// Resolve a BCP 47 language tag such as `fr`, `fr-CA` or `de_AT`.
+    pub(crate) fn from_lang(lang: Option<&str>) -> Self {
+        let Some(lang) = lang else {
+            return Locale::English;
+        };
+        let primary = lang.split(['-', '_']).next().unwrap_or_default();
+        match primary.to_ascii_lowercase().as_str() {
+            "" | "en" => Locale::English,
+            "fr" => Locale::French,
+            "de" => Locale::German,
+            _ => Locale::Other,
+        }
+    }
+
+    /// Longest word that is bound to the following word with a non-breaking space.
+    fn short_word_len(self) -> usize {
+        match self {
+            Locale::French | Locale::German => 2,
+            Locale::English | Locale::Other => 1,
+        }
+    }
+}
+
+/// Text that passes rewriting prose (typography, emoji shortcodes) copy
+/// verbatim: bare URLs and requirement markers.
+pub(crate) const VERBATIM_PATTERN: &str = r"[A-Za-z][A-Za-z0-9+.-]*://\S*|\b[a-z0-9]+\[[^\]]+\]";
+
+fn skip_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(VERBATIM_PATTERN).unwrap())
+}
+
+fn unit_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(
+            // Units sharing a prefix are listed longest first, as the first
+            // alternative that matches wins
+            r"(\d)[ \u{a0}](%|‰|°[CF]?|[kKMGT]i?B|B|[kMG]?Hz|k?W|mA|mm|mg|ms|ml|min|m|V|km|cm|nm|µm|kg|g|µs|ns|s|h|L|px|rem|em|pt|€)",
+        )
+        .unwrap()
+    })
+}
+
+/// Apply typography rules for `locale` to plain (unescaped) text.
+pub(crate) fn apply(text: &str, locale: Locale) -> Cow<'_, str> {
+    if text.trim().is_empty() {
+        return Cow::Borrowed(text);
+    }
+
+    let mut out = String::with_capacity(text.len() + 8);
+    let mut copied = 0;
+    for skip in skip_regex().find_iter(text) {
+        transform(&mut out, &text[copied..skip.start()], locale);
+        out.push_str(skip.as_str());
+        copied = skip.end();
+    }
+    transform(&mut out, &text[copied..], locale);
+
+    if out == text {
+        Cow::Borrowed(text)
+    } else {
+        Cow::Owned(out)
+    }
+}
+
+fn transform(out: &mut String, text: &str, locale: Locale) {
+    if text.is_empty() {
+        return;
+    }
+
+    // Spaced hyphens become dashes; dashes and ellipses never start a line
+    let mut text = text
+        .replace(" - ", " – ")
+        .replace(" –", "\u{a0}–")
+        .replace(" —", "\u{a0}—")
+        .replace(" …", "\u{a0}…");
+
+    match locale {
+        Locale::French => {
+            text = text
+                .replace(['“', '«'], "«\u{a0}")
+                .replace(['”', '»'], "\u{a0}»")
+                .replace("«\u{a0} ", "«\u{a0}")
+                .replace(" \u{a0}»", "\u{a0}»");
+            text = french_spacing(&text);
+        }
+        Locale::German => {
+            text = text.replace('“', "„").replace('”', "“");
+        }
+        Locale::English | Locale::Other => {}
+    }
+
+    let text = unit_regex().replace_all(&text, |caps: &Captures<'_>| {
+        let whole = caps.get(0).unwrap();
+        let glued = text[whole.end()..]
+            .chars()
+            .next()
+            .is_some_and(char::is_alphanumeric);
+        if glued {
+            whole.as_str().to_string()
+        } else {
+            format!("{}{}{}", &caps[1], NBSP, &caps[2])
+        }
+    });
+
+    bind_short_words(out, &text, locale.short_word_len());
+}
+
+/// Non-breaking spaces before `:` and narrow ones before `;`, `!` and `?`.
+fn french_spacing(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if matches!(c, ' ' | NBSP | NARROW_NBSP) {
+            match chars.peek() {
+                Some(':') => {
+                    out.push(NBSP);
+                    continue;
+                }
+                Some(';' | '!' | '?') => {
+                    out.push(NARROW_NBSP);
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Replace the space after words of at most `max_len` letters with a non-breaking space.
+fn bind_short_words(out: &mut String, text: &str, max_len: usize) {
+    let mut word_len = 0;
+    let mut word_bindable = false;
+    let mut prev: Option<char> = None;
+
+    for c in text.chars() {
+        if c == ' ' && word_bindable && (1..=max_len).contains(&word_len) {
+            out.push(NBSP);
+            word_len = 0;
+            prev = Some(NBSP);
+            continue;
+        }
+
+        if c.is_alphabetic() {
+            if word_len == 0 {
+                word_bindable = prev.is_none_or(|p| {
+                    p.is_whitespace() || matches!(p, '(' | '[' | '“' | '„' | '«' | '‘' | '‚')
+                });
+            }
+            word_len += 1;
+        } else {
+            word_len = 0;
+            word_bindable = false;
+        }
+        out.push(c);
+        prev = Some(c);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locale_from_lang() {
+        assert_eq!(Locale::from_lang(None), Locale::English);
+        assert_eq!(Locale::from_lang(Some("fr-CA")), Locale::French);
+        assert_eq!(Locale::from_lang(Some("de_AT")), Locale::German);
+        assert_eq!(Locale::from_lang(Some("nl")), Locale::Other);
+    }
+
+    #[test]
+    fn test_french_spacing_and_quotes() {
+        assert_eq!(
+            apply("Il dit “bonjour” : vraiment ? Oui !", Locale::French),
+            "Il\u{a0}dit «\u{a0}bonjour\u{a0}»\u{a0}: vraiment\u{202f}? Oui\u{202f}!"
+        );
+    }
+
+    #[test]
+    fn test_german_quotes() {
+        assert_eq!(
+            apply("Er sagte “Hallo”.", Locale::German),
+            "Er\u{a0}sagte „Hallo“."
+        );
+    }
+
+    #[test]
+    fn test_units_dashes_and_short_words() {
+        assert_eq!(
+            apply("It weighs 5 kg - roughly 11 lb, a lot …", Locale::English),
+            "It weighs 5\u{a0}kg\u{a0}– roughly 11 lb, a\u{a0}lot\u{a0}…"
+        );
+        assert_eq!(apply("5 kgs of 3 mice", Locale::English), "5 kgs of 3 mice");
+    }
+
+    #[test]
+    fn test_units_sharing_a_prefix() {
+        for unit in ["m", "mA", "mm", "mg", "ms", "ml", "min"] {
+            assert_eq!(
+                apply(&format!("It took 5 {unit}."), Locale::English),
+                format!("It took 5\u{a0}{unit}."),
+                "{unit}"
+            );
+        }
+        assert_eq!(apply("5 mins", Locale::English), "5 mins");
+    }
+
+    #[test]
+    fn test_skips_urls() {
+        assert_eq!(
+            apply("See https://example.com/a - b", Locale::French),
+            "See https://example.com/a\u{a0}– b"
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! Variable substitution in markdown text.
+//!
+//! Expands `{{ page.title }}`, `{{ extra.version }}` and caller-provided
+//! variables in text events, so substituted values never affect parsing or
+//! source offsets. Unknown variables are left as written.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use facet_value::{DestructuredRef, Value};
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd};
+use regex::{Captures, Regex};
+
+use crate::frontmatter::Frontmatter;
+
+fn variable_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"\{\{\s*([A-Za-z_][A-Za-z0-9_.-]*)\s*\}\}").unwrap())
+}
+
+/// Expands variable references in the event stream of one document.
+pub(crate) struct VariableExpander<'v> {
+    frontmatter: Option<&'v Frontmatter>,
+    variables: &'v BTreeMap<String, String>,
+    /// Also expand inside code spans and code blocks
+    in_code: bool,
+    in_code_block: bool,
+    in_metadata: bool,
+}
+
+impl<'v> VariableExpander<'v> {
+    pub(crate) fn new(
+        frontmatter: Option<&'v Frontmatter>,
+        variables: &'v BTreeMap<String, String>,
+        in_code: bool,
+    ) -> Self {
+        Self {
+            frontmatter,
+            variables,
+            in_code,
+            in_code_block: false,
+            in_metadata: false,
+        }
+    }
+
+    /// Expand variables in one event.
+    pub(crate) fn expand_event<'a>(&mut self, event: Event<'a>) -> Event<'a> {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => self.in_code_block = true,
+            Event::End(TagEnd::CodeBlock) => self.in_code_block = false,
+            Event::Start(Tag::MetadataBlock(_)) => self.in_metadata = true,
+            Event::End(TagEnd::MetadataBlock(_)) => self.in_metadata = false,
+            Event::Text(text) if !self.in_metadata && (!self.in_code_block || self.in_code) => {
+                return Event::Text(self.expand_cow(text));
+            }
+            Event::Code(code) if self.in_code => return Event::Code(self.expand_cow(code)),
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => {
+                return Event::Start(Tag::Link {
+                    link_type,
+                    dest_url: self.expand_cow(dest_url),
+                    title: self.expand_cow(title),
+                    id,
+                });
+            }
+            Event::Start(Tag::Image {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => {
+                return Event::Start(Tag::Image {
+                    link_type,
+                    dest_url: self.expand_cow(dest_url),
+                    title: self.expand_cow(title),
+                    id,
+                });
+            }
+            _ => {}
+        }
+        event
+    }
+
+    fn expand_cow<'a>(&self, text: CowStr<'a>) -> CowStr<'a> {
+        match self.expand(&text) {
+            Cow::Borrowed(_) => text,
+            Cow::Owned(expanded) => CowStr::from(expanded),
+        }
+    }
+
+    /// Expand variable references in a string.
+    pub(crate) fn expand<'t>(&self, text: &'t str) -> Cow<'t, str> {
+        if !text.contains("{{") {
+            return Cow::Borrowed(text);
+        }
+        variable_regex().replace_all(text, |caps: &Captures<'_>| {
+            self.lookup(&caps[1]).unwrap_or_else(|| caps[0].to_string())
+        })
+    }
+
+    /// Look up a variable: caller-provided names first, then `page.*` and `extra.*`.
+    fn lookup(&self, name: &str) -> Option<String> {
+        if let Some(value) = self.variables.get(name) {
+            return Some(value.clone());
+        }
+
+        let frontmatter = self.frontmatter?;
+        match name.split_once('.')? {
+            ("page", "title") => Some(frontmatter.title.clone()),
+            ("page", "weight") => Some(frontmatter.weight.to_string()),
+            ("page", "description") => frontmatter.description.clone(),
+            ("page", "template") => frontmatter.template.clone(),
+            ("extra", path) => {
+                let mut value = &frontmatter.extra;
+                for key in path.split('.') {
+                    value = match value.destructure_ref() {
+                        DestructuredRef::Object(obj) => obj.get(key)?,
+                        _ => return None,
+                    };
+                }
+                scalar_to_string(value)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn scalar_to_string(value: &Value) -> Option<String> {
+    if let Some(s) = value.as_string() {
+        return Some(s.as_str().to_string());
+    }
+    if let Some(b) = value.as_bool() {
+        return Some(b.to_string());
+    }
+    let n = value.as_number()?;
+    if n.is_float() {
+        // Debug keeps the written form of whole floats (`1.0`, not `1`)
+        return n.to_f64().map(|f| format!("{f:?}"));
+    }
+    n.to_i64()
+        .map(|n| n.to_string())
+        .or_else(|| n.to_u64().map(|n| n.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_caller_variables() {
+        let variables = BTreeMap::from([("version".to_string(), "1.4.2".to_string())]);
+        let expander = VariableExpander::new(None, &variables, false);
+
+        assert_eq!(
+            expander.expand("cargo add marq@{{ version }} {{version}}"),
+            "cargo add marq@1.4.2 1.4.2"
+        );
+        assert_eq!(
+            expander.expand("{{ page.title }} {{ unknown }}"),
+            "{{ page.title }} {{ unknown }}"
+        );
+    }
+
+    #[test]
+    fn test_expand_page_fields() {
+        let frontmatter = Frontmatter {
+            title: "Install".to_string(),
+            weight: 3,
+            ..Default::default()
+        };
+        let variables = BTreeMap::new();
+        let expander = VariableExpander::new(Some(&frontmatter), &variables, false);
+
+        assert_eq!(
+            expander.expand("{{ page.title }} ({{ page.weight }})"),
+            "Install (3)"
+        );
+    }
+
+    #[test]
+    fn test_expand_numeric_extra_fields() {
+        let frontmatter: Frontmatter = facet_toml::from_str(
+            "title = \"Install\"\n\n[extra]\nrust-version = 1.85\nedition = 2024\nmajor = 2.0\n",
+        )
+        .unwrap();
+        let variables = BTreeMap::new();
+        let expander = VariableExpander::new(Some(&frontmatter), &variables, false);
+
+        assert_eq!(
+            expander.expand(
+                "MSRV {{ extra.rust-version }}, edition {{ extra.edition }}, v{{ extra.major }}"
+            ),
+            "MSRV 1.85, edition 2024, v2.0"
+        );
+        assert_eq!(
+            scalar_to_string(&Value::from(u64::MAX)).as_deref(),
+            Some("18446744073709551615")
+        );
+    }
+
+    #[test]
+    fn test_code_left_alone_unless_requested() {
+        let variables = BTreeMap::from([("v".to_string(), "1".to_string())]);
+
+        let mut expander = VariableExpander::new(None, &variables, false);
+        assert_eq!(
+            expander.expand_event(Event::Code("{{ v }}".into())),
+            Event::Code("{{ v }}".into())
+        );
+
+        let mut expander = VariableExpander::new(None, &variables, true);
+        assert_eq!(
+            expander.expand_event(Event::Code("{{ v }}".into())),
+            Event::Code("1".into())
+        );
+    }
+}
@@ -394,3 +394,118 @@ second = 2
     // Should have 2 code samples
     assert_eq!(doc.code_samples.len(), 2);
 }
+
+#[tokio::test]
+async fn test_compare_section_labels() {
+    let markdown = r#"
+```compare
+/// rust "Before"
+let x = vec![1, 2, 3];
+/// rust "After"
+let x = [1, 2, 3];
+```
+"#;
+
+    let doc = render_with_compare(markdown).await;
+
+    assert!(doc.html.contains(r#"class="compare-header">Before</div>"#));
+    assert!(doc.html.contains(r#"class="compare-header">After</div>"#));
+    assert_eq!(doc.html.matches(r#"class="language-rust""#).count(), 2);
+    assert!(doc.head_injections.is_empty());
+}
+
+#[tokio::test]
+async fn test_compare_tabs() {
+    let markdown = r#"
+```compare,tabs
+/// json
+{"a": 1}
+/// yaml "YAML"
+a: 1
+```
+
+```compare
+/// json
+{"b": 2}
+/// yaml
+b: 2
+```
+"#;
+
+    let doc = render_with_compare(markdown).await;
+
+    // Only the flagged block is tabbed
+    assert_eq!(doc.html.matches(r#"data-tabs="compare""#).count(), 1);
+    assert!(doc.html.contains(
+        r#"<button class="compare-tab" type="button" role="tab" aria-selected="true" data-tab="json">json</button>"#
+    ));
+    assert!(
+        doc.html
+            .contains(r#"aria-selected="false" data-tab="YAML">YAML</button>"#)
+    );
+    assert!(doc.html.contains(r#"role="tabpanel" data-tab="YAML""#));
+    // Sections are not hidden in the markup, so they stay readable without JS
+    assert!(!doc.html.contains(" hidden"));
+    assert_eq!(doc.head_injections.len(), 1);
+    assert!(doc.head_injections[0].contains("[data-tabs]"));
+
+    let opts =
+        RenderOptions::new().with_handler(&["compare"], CompareHandler::new().with_tabs(true));
+    let doc = render(markdown, &opts).await.unwrap();
+    assert_eq!(doc.html.matches(r#"data-tabs="compare""#).count(), 2);
+    assert_eq!(doc.head_injections.len(), 1);
+}
+
+#[tokio::test]
+async fn test_compare_tabs_share_script_with_code_groups() {
+    let markdown = r#"
+```compare,tabs
+/// json
+{"a": 1}
+/// yaml
+a: 1
+```
+
+```bash,group
+echo bash
+```
+```fish,group
+echo fish
+```
+"#;
+
+    let doc = render_with_compare(markdown).await;
+
+    assert!(doc.html.contains(r#"data-tabs="compare""#));
+    assert!(doc.html.contains(r#"data-tabs="code-group""#));
+    // Both widgets are driven by the same script, injected once
+    assert_eq!(doc.head_injections.len(), 1);
+}
+
+#[tokio::test]
+async fn test_compare_tabs_with_repeated_titles() {
+    let markdown = r#"
+```compare,tabs
+/// json
+{"a": 1}
+/// json
+{"a": 2}
+```
+"#;
+
+    let doc = render_with_compare(markdown).await;
+
+    assert!(
+        doc.html
+            .contains(r#"aria-selected="false" data-tab="json-2">json</button>"#),
+        "{}",
+        doc.html
+    );
+    assert_eq!(
+        doc.html
+            .matches(r#"role="tabpanel" data-tab="json""#)
+            .count(),
+        1
+    );
+    assert!(doc.html.contains(r#"role="tabpanel" data-tab="json-2""#));
+}